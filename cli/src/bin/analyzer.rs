@@ -0,0 +1,297 @@
+extern crate chrono;
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use chrono::prelude::*;
+use std::path::Path;
+use structopt::StructOpt;
+
+use cli::display::Display;
+use tetris20g_ai::analysis::{analyze, Candidate};
+use tetris20g_ai::core::{self, Command, FixedInfo, Position, HEIGHT, WIDTH};
+use tetris20g_ai::enumeration::find_command_sequence;
+use tetris20g_ai::fumen;
+use tetris20g_ai::regressor::{load_model, Explanation, MAX_DJ};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "analyzer",
+            about = "Edit a field and analyze the placements of the current and next pieces.")]
+struct Opt {
+    #[structopt(long = "file", default_value = "resources/weights__1.txt",
+                help = "Weights file name.")]
+    file: String,
+
+    #[structopt(long = "position",
+                help = "Position to start from, given by a file, the text notation or a fumen.")]
+    position: Option<String>,
+
+    #[structopt(long = "dir", default_value = "positions",
+                help = "Directory where positions are saved.")]
+    dir: String,
+}
+
+/// The number of candidates listed at once.
+const LISTED: usize = 10;
+
+/// What the heatmap of the field shows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Heatmap {
+    Off,
+    /// Contributions to the value of the selected candidate.
+    Value,
+    /// Contributions to the difference from the best candidate, or the second best one if the
+    /// best one is selected.
+    Diff,
+}
+
+/// Reads a position from a file, the text notation, e.g., `XXXX1XXXXX:TI`, or a fumen, whose
+/// first page gives the field and whose pieces give the queue.
+fn read_position(source: &str) -> Result<Position, String> {
+    let position = if Path::new(source).is_file() {
+        Position::read(source)?
+    } else if source.contains("115@") {
+        fumen::position(&fumen::decode(source)?)
+    } else {
+        source.parse()?
+    };
+    if position.hold.is_some() {
+        return Err(String::from("hold is not supported"));
+    }
+    Ok(position)
+}
+
+/// Formats commands, e.g., `<<AF` moves left twice, rotates and fixes the piece. `B` rotates in
+/// the other direction.
+fn format_commands(commands: &[Command]) -> String {
+    let mut text = String::new();
+    for command in commands {
+        match *command {
+            Command::Move(dx, rotate) => {
+                text += if dx < 0 { "<" } else if dx > 0 { ">" } else { "" };
+                text += if rotate > 0 { "A" } else if rotate < 0 { "B" } else { "" };
+            }
+            Command::Fix => text += "F",
+        }
+    }
+    text
+}
+
+fn format_placement(info: &FixedInfo) -> String {
+    let state = &info.last_state;
+    format!("{}{}@{}", state.piece_type as char, state.rotation, state.x)
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let model = load_model(&opt.file);
+    let mut field = core::EMPTY_FIELD;
+    let mut pieces = [b'T', b'I'];
+    if let Some(ref source) = opt.position {
+        let position = read_position(source).unwrap_or_else(|e| panic!("--position {}", e));
+        field = position.field;
+        for (piece, &queued) in pieces.iter_mut().zip(position.queue.iter()) {
+            *piece = queued;
+        }
+    }
+
+    let display = Display::new();
+    let mut candidates: Vec<Candidate> = vec![];
+    let mut changed = true;
+    let mut selected = 0;
+    let mut cursor = (HEIGHT - 1, 0);
+    let mut preview = false;
+    let mut heatmap = Heatmap::Off;
+    let mut prompt: Option<String> = None;
+    let mut message: Option<String> = None;
+    loop {
+        if changed {
+            candidates = analyze(model.as_ref(), &field, &pieces);
+            selected = 0;
+            changed = false;
+        }
+        let candidate = candidates.get(selected);
+        let position = Position { field, queue: pieces.to_vec(), hold: None };
+
+        let explanation: Option<(Explanation, String)> = match (heatmap, candidate) {
+            (Heatmap::Off, _) | (_, None) => None,
+            (Heatmap::Value, Some(c)) => model
+                .explain(c.field())
+                .map(|e| (e, format!("Heatmap: value of {}", selected + 1))),
+            (Heatmap::Diff, Some(c)) => {
+                let base = if selected == 0 { 1 } else { 0 };
+                candidates.get(base).and_then(|b| {
+                    let diff = model.explain(c.field())?.diff(&model.explain(b.field())?);
+                    Some((diff, format!("Heatmap: {} minus {}", selected + 1, base + 1)))
+                })
+            }
+        };
+
+        display.erase();
+        match candidate {
+            Some(candidate) if preview => {
+                display.draw_field(candidate.field(), &core::new_piece(pieces[0]), None)
+            }
+            Some(candidate) => {
+                let state = &candidate.placements[0].last_state;
+                display.draw_field(&field, state, Some(pieces[1]));
+            }
+            None => display.draw_field(&field, &core::new_piece(pieces[0]), Some(pieces[1])),
+        }
+        if let (Some((ref explanation, _)), Some(candidate)) = (&explanation, candidate) {
+            display.draw_heatmap(candidate.field(), &explanation.by_cell);
+        } else if !preview {
+            display.draw_cursor(&field, cursor.0, cursor.1);
+        }
+        let text = format!("Current: {}  Next: {}", pieces[0] as char, pieces[1] as char);
+        display.draw_text(0, &text);
+        display.draw_text(1, &format!("Candidates: {}", candidates.len()));
+        if let Some((ref explanation, ref title)) = explanation {
+            display.draw_text(2, &format!("{}: {:.3}", title, explanation.value));
+            display.draw_text(3, &format!("By offset, dj from {} to {}:", -MAX_DJ, MAX_DJ));
+            for (di, row) in explanation.by_offset.iter().enumerate() {
+                let values: Vec<String> = row.iter().map(|v| format!("{:7.2}", v)).collect();
+                display.draw_text(4 + di as i32, &format!("di={} {}", di, values.join("")));
+            }
+            let half = HEIGHT / 2;
+            display.draw_text(9, &format!(
+                "Upper half: {:.3}  Lower half: {:.3}",
+                explanation.region(0..half, 0..WIDTH),
+                explanation.region(half..HEIGHT, 0..WIDTH)
+            ));
+            display.draw_text(10, &format!("Features: {}", explanation.features.len()));
+        } else {
+            let first = selected / LISTED * LISTED;
+            for (i, c) in candidates.iter().enumerate().skip(first).take(LISTED) {
+                let marker = if i == selected { ">" } else { " " };
+                let placements: Vec<String> = c.placements.iter().map(format_placement).collect();
+                let text =
+                    format!("{}{:4} {:9.3} {}", marker, i + 1, c.value, placements.join(" "));
+                display.draw_text(2 + (i - first) as i32, &text);
+            }
+        }
+        if let Some(candidate) = candidate {
+            display.draw_text(13, &format!("Value: {:.3}", candidate.value));
+            display.draw_text(14, &format!(
+                "Lines: {}  Height: {}  Holes: {}",
+                candidate.lines, candidate.height, candidate.holes
+            ));
+            let mut before = field;
+            for (i, info) in candidate.placements.iter().enumerate() {
+                let commands = find_command_sequence(&before, pieces[i], &info.last_state);
+                let label = if i == 0 { "Inputs" } else { "Then" };
+                let text = format!("{}: {}", label, format_commands(&commands));
+                display.draw_text(15 + i as i32, &text);
+                before = info.new_field;
+            }
+        }
+        display.draw_text(18, &position.to_string());
+        if let Some(ref text) = prompt {
+            display.draw_text(19, &format!("Load: {}", text));
+        } else if let Some(ref text) = message {
+            display.draw_text(19, text);
+        }
+        display.draw_text(20, "arrows:cursor space:cell r:row e:erase");
+        display.draw_text(21, "iosz jlt:current IOSZJLT:next");
+        display.draw_text(22, "[/]:select v:preview h:heatmap enter:play");
+        display.draw_text(23, "w:save g:load f:fumen q:quit");
+        display.refresh();
+
+        let key = match display.wait_key_name() {
+            Some(key) => key,
+            None => continue,
+        };
+        if let Some(mut text) = prompt.take() {
+            match key.as_str() {
+                "enter" => match read_position(text.trim()) {
+                    Ok(loaded) => {
+                        field = loaded.field;
+                        for (piece, &queued) in pieces.iter_mut().zip(loaded.queue.iter()) {
+                            *piece = queued;
+                        }
+                        changed = true;
+                        message = Some(String::from("Loaded"));
+                    }
+                    Err(e) => message = Some(e),
+                },
+                "\u{1b}" => (),
+                "backspace" => {
+                    text.pop();
+                    prompt = Some(text);
+                }
+                "space" => prompt = Some(text + " "),
+                key if key.chars().count() == 1 => prompt = Some(text + key),
+                _ => prompt = Some(text),
+            }
+            continue;
+        }
+        message = None;
+        match key.as_str() {
+            "up" => cursor.0 = cursor.0.saturating_sub(1),
+            "down" => cursor.0 = (cursor.0 + 1).min(HEIGHT - 1),
+            "left" => cursor.1 = cursor.1.saturating_sub(1),
+            "right" => cursor.1 = (cursor.1 + 1).min(WIDTH - 1),
+            "space" => {
+                let cell = &mut field[cursor.0][cursor.1];
+                *cell = if *cell == b'.' { b'X' } else { b'.' };
+                changed = true;
+            }
+            "r" => {
+                let row = &mut field[cursor.0];
+                let fill = row.iter().all(|&cell| cell == b'.');
+                for (j, cell) in row.iter_mut().enumerate() {
+                    *cell = if fill && j != cursor.1 { b'X' } else { b'.' };
+                }
+                changed = true;
+            }
+            "e" => {
+                field = core::EMPTY_FIELD;
+                changed = true;
+            }
+            "]" => selected = (selected + 1).min(candidates.len().saturating_sub(1)),
+            "[" => selected = selected.saturating_sub(1),
+            "v" => preview = !preview,
+            "h" => {
+                heatmap = match heatmap {
+                    Heatmap::Off => Heatmap::Value,
+                    Heatmap::Value => Heatmap::Diff,
+                    Heatmap::Diff => Heatmap::Off,
+                };
+                if heatmap != Heatmap::Off && model.explain(&field).is_none() {
+                    heatmap = Heatmap::Off;
+                    message = Some(String::from("The model cannot explain values"));
+                }
+            }
+            "enter" => {
+                if let Some(candidate) = candidate {
+                    field = candidate.placements[0].new_field;
+                    pieces.swap(0, 1);
+                    changed = true;
+                }
+            }
+            "w" => {
+                let file = format!("{}/{}.json", opt.dir, Local::now().format("%Y%m%d-%H%M%S"));
+                position.save(&file);
+                message = Some(format!("Saved to {}", file));
+            }
+            "g" => prompt = Some(String::new()),
+            "f" => {
+                let states: Vec<_> = candidate
+                    .map(|c| c.placements.iter().map(|info| info.last_state.clone()).collect())
+                    .unwrap_or_default();
+                message = Some(fumen::encode_placements(&field, &states));
+            }
+            "q" => break,
+            key => {
+                let piece = key.to_uppercase();
+                if key.len() == 1 && core::parse_piece(&piece).is_ok() {
+                    let index = if key == piece { 1 } else { 0 };
+                    pieces[index] = piece.as_bytes()[0];
+                    changed = true;
+                }
+            }
+        }
+    }
+}
@@ -7,10 +7,18 @@ extern crate structopt_derive;
 extern crate tetris20g_ai;
 
 use chrono::prelude::*;
+use std::fs;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
 use cli::display::Display;
-use tetris20g_ai::human_manipulation::Game;
+use tetris20g_ai::agent::Hint;
+use tetris20g_ai::core::{self, Position, Rules};
+use tetris20g_ai::human_manipulation::{Game, Phase};
+use tetris20g_ai::input_profile::{Action, InputConfig};
+use tetris20g_ai::logger::{ExistingLog, Logger, SessionInfo};
+use tetris20g_ai::regressor::{load_model, ValueModel};
+use tetris20g_ai::replay::ReplayWriter;
 use tetris20g_ai::utility;
 
 #[derive(StructOpt, Debug)]
@@ -25,24 +33,227 @@ struct Opt {
 
     #[structopt(long = "no-save", help = "The program will not save log data if true.")]
     no_save: bool,
+
+    #[structopt(long = "resume",
+                help = "Append to the save file if it exists for the same seed, lines and player. \
+Otherwise an existing save file is renamed.")]
+    resume: bool,
+
+    #[structopt(long = "input-config",
+                help = "Input config file with key bindings of players (e.g., \
+`resources/input_profiles.json`). The profile of `--player` or `default` is used.")]
+    input_config: Option<String>,
+
+    #[structopt(long = "player", help = "Player name recorded in the log.")]
+    player: Option<String>,
+
+    #[structopt(long = "seed", parse(try_from_str = "utility::parse_seed"),
+                help = "Seed for the piece sequence and the filled lines, other than 0.")]
+    seed: Option<u32>,
+
+    #[structopt(long = "replay-dir", help = "Directory where a replay file is saved.")]
+    replay_dir: Option<String>,
+
+    #[structopt(long = "hint-weights",
+                help = "Weights file of a model whose suggestions are shown during play.")]
+    hint_weights: Option<String>,
+
+    #[structopt(long = "real-time",
+                help = "Lock pieces after the lock delay instead of waiting for keys.")]
+    real_time: bool,
+
+    #[structopt(long = "lock-delay", default_value = "30",
+                help = "Lock delay in frames for real-time play.")]
+    lock_delay: u32,
+
+    #[structopt(long = "are", default_value = "25",
+                help = "Frames before the next piece appears in real-time play.")]
+    are: u32,
+
+    #[structopt(long = "practice",
+                help = "Position file to start from instead of the filled lines. A restart \
+starts from the same position.")]
+    practice: Option<String>,
+
+    #[structopt(long = "practice-dir", default_value = "practice",
+                help = "Directory where positions are saved by the save-position key.")]
+    practice_dir: String,
 }
 
+/// The number of pieces in the queue of a saved position.
+const SAVED_QUEUE: usize = 7;
+
 fn main() {
     let opt = Opt::from_args();
 
-    let seq = utility::generate_pieces(100000, None);
-    let save_file: Option<String> = save_file_name(&opt);
-    let mut game = Game::new(seq, save_file);
-    game.field = utility::filled_field(opt.lines, None);
-
+    let mut overlay = opt.hint_weights.as_ref().map(|file| Overlay::new(load_model(file)));
     let display = Display::new();
+    loop {
+        let mut game = new_game(&opt);
+        if !play(&display, &mut game, &mut overlay, &opt) {
+            break;
+        }
+    }
+}
+
+fn new_game(opt: &Opt) -> Game {
+    let seed = opt.seed.unwrap_or_else(utility::random_seed);
+    let rules = if opt.real_time {
+        Rules::real_time(opt.lock_delay, opt.are)
+    } else {
+        Rules::default()
+    };
+    let config = opt.input_config.as_ref().map_or_else(InputConfig::default, |file| {
+        InputConfig::load(file)
+    });
+    let profile = config.profile_for(opt.player.as_deref());
+    let session = SessionInfo {
+        seed,
+        randomizer: String::from(utility::RANDOMIZER),
+        rules: rules.clone(),
+        lines: opt.lines,
+        player: opt.player.clone(),
+        date: Local::now().to_rfc3339(),
+        input_profile: Some(profile.clone()),
+        position: opt.practice.as_ref().map(|file| {
+            let position = Position::load(file);
+            assert!(position.hold.is_none(), "hold is not supported");
+            position
+        }),
+    };
+    let existing = if opt.resume { ExistingLog::Resume } else { ExistingLog::Rotate };
+    let logger = save_file_name(opt)
+        .map(|file| Logger::open(&file, Some(&session), existing).unwrap());
+    let replay = opt.replay_dir.as_ref().map(|dir| {
+        fs::create_dir_all(dir).unwrap();
+        ReplayWriter::create(&format!("{}/{}.replay", dir, timestamp()), &session)
+    });
+    let mut game = Game::new(session.pieces(100000), logger, replay);
+    game.field = session.initial_field();
+    game.rules = rules;
+    game.profile = profile;
+    game
+}
+
+/// Plays a game until it is quit or restarted. Returns true if it is restarted.
+/// The game is dropped by the caller, which saves the last piece.
+fn play(display: &Display, game: &mut Game, overlay: &mut Option<Overlay>, opt: &Opt) -> bool {
+    let frame = Duration::from_nanos(1_000_000_000 / 60);
+    let mut next_frame = Instant::now() + frame;
+    let mut message: Option<String> = None;
+    if let Some(ref mut overlay) = overlay {
+        overlay.reset();
+    }
     loop {
         display.erase();
         display.draw_field(&game.field, &game.state, game.next_piece());
+        display.draw_score_info(&game.score_info);
+        if let Some(ref mut overlay) = overlay {
+            overlay.update(game);
+            overlay.draw(display, game);
+        }
+        if game.is_game_over() {
+            display.draw_text(18, "GAME OVER");
+            display.draw_text(20, "enter: restart");
+            display.draw_text(21, "q: quit");
+        }
+        if let Some(ref message) = message {
+            display.draw_text(23, message);
+        }
         display.refresh();
-        let key = display.wait_key();
-        if let Some(key) = key {
-            game.input(key);
+
+        let key = if game.is_game_over() {
+            display.wait_key_name()
+        } else if !opt.real_time {
+            // wake up from time to time to sync the log
+            let key = display.poll_key_name(1000);
+            game.sync_log();
+            key
+        } else {
+            let now = Instant::now();
+            if now >= next_frame {
+                game.tick();
+                next_frame += frame;
+                continue;
+            }
+            let wait = next_frame - now;
+            display.poll_key_name((wait.as_millis() as i32).max(1))
+        };
+        match key.as_deref() {
+            Some("q") => return false,
+            Some("enter") if game.is_game_over() => return true,
+            Some(key) => {
+                message = None;
+                if game.profile.action(key) == Some(Action::SavePosition) {
+                    let file = format!("{}/{}.json", opt.practice_dir, timestamp());
+                    game.position(SAVED_QUEUE).save(&file);
+                    message = Some(format!("Saved {}", file));
+                }
+                game.input(key);
+            }
+            None => (),
+        }
+    }
+}
+
+/// Suggestions of a model shown during play.
+struct Overlay {
+    model: Box<dyn ValueModel>,
+    /// The index of the piece the hint is for, and the hint.
+    hint: Option<(usize, Hint)>,
+    /// The rank and the value of the last decided piece, the number of its placements, and the
+    /// best value.
+    last: Option<(usize, f32, usize, f32)>,
+}
+
+impl Overlay {
+    fn new(model: Box<dyn ValueModel>) -> Overlay {
+        Overlay { model, hint: None, last: None }
+    }
+
+    fn reset(&mut self) {
+        self.hint = None;
+        self.last = None;
+    }
+
+    /// Evaluates the current piece when a new piece comes.
+    fn update(&mut self, game: &Game) {
+        let id = game.current_piece_id;
+        if self.hint.as_ref().is_some_and(|(hint_id, _)| *hint_id == id) || game.is_game_over() {
+            return;
+        }
+        if let (Some((hint_id, hint)), Some(decided)) = (self.hint.take(), &game.last_decided) {
+            if hint_id + 1 == id {
+                self.last = hint.rank(decided).map(|(rank, value)| {
+                    (rank, value, hint.placements.len(), hint.best().unwrap().1)
+                });
+            }
+        }
+        if let Some(next_piece) = game.next_piece() {
+            let piece = game.state.piece_type;
+            let hint = Hint::new(self.model.as_ref(), &game.field, piece, next_piece);
+            self.hint = Some((id, hint));
+        }
+    }
+
+    fn draw(&self, display: &Display, game: &Game) {
+        if let Some((_, ref hint)) = self.hint {
+            if let Some((best, value)) = hint.best() {
+                display.draw_ghost(&game.field, best, &game.state);
+                display.draw_text(1, &format!("AI:   {:8.3}", value));
+            }
+            let hovered = match core::apply_command(&game.field, &game.state, &core::Command::Fix) {
+                core::CommandResult::Fixed(info) if game.phase == Phase::Active => {
+                    hint.rank(&info.last_state)
+                }
+                _ => None,
+            };
+            if let Some((rank, value)) = hovered {
+                display.draw_text(2, &format!("You:  {:8.3} #{}", value, rank));
+            }
+        }
+        if let Some((rank, value, count, best)) = self.last {
+            display.draw_text(3, &format!("Last: #{}/{} {:+.3}", rank, count, value - best));
         }
     }
 }
@@ -59,7 +270,9 @@ fn save_file_name(opt: &Opt) -> Option<String> {
     }
 }
 
+/// Returns the current time for file names. Milliseconds are included, since a game can be
+/// restarted within a second after the last one ended.
 fn timestamp() -> String {
     let local: DateTime<Local> = Local::now();
-    String::from(local.format("%Y%m%d-%H%M%S").to_string())
+    local.format("%Y%m%d-%H%M%S-%3f").to_string()
 }
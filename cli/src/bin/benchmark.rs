@@ -0,0 +1,106 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use std::sync::Arc;
+use std::time::Instant;
+use structopt::StructOpt;
+
+use tetris20g_ai::agent::TwoStepSearchAgent;
+use tetris20g_ai::benchmark::{parse_seeds, Report, METRICS, PERCENTILES};
+use tetris20g_ai::episode::{run_parallel, run_seeded_episode};
+use tetris20g_ai::regressor::{load_model, ValueModel};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "benchmark",
+            about = "Run seeded episodes of an agent in parallel and report their statistics.")]
+struct Opt {
+    #[structopt(long = "file", help = "Weights file name.")]
+    file: String,
+
+    #[structopt(long = "seeds", default_value = "1-100",
+                help = "Seeds of the episodes, e.g., `1-100,200,300-309`.")]
+    seeds: String,
+
+    #[structopt(long = "lines", default_value = "0",
+                help = "The number of lines initially filled at random.")]
+    lines: usize,
+
+    #[structopt(long = "max-steps",
+                help = "Episodes are truncated after this number of pieces.")]
+    max_steps: Option<usize>,
+
+    #[structopt(long = "threads", default_value = "4", help = "The number of worker threads.")]
+    threads: usize,
+
+    #[structopt(long = "json", help = "Output file name for the report in JSON.")]
+    json: Option<String>,
+
+    #[structopt(long = "csv", help = "Output file name for the episode records in CSV.")]
+    csv: Option<String>,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let seeds = parse_seeds(&opt.seeds).unwrap_or_else(|e| panic!("--seeds {}", e));
+    assert!(!seeds.is_empty(), "no seeds");
+
+    let start = Instant::now();
+    let model: Arc<dyn ValueModel> = Arc::from(load_model(&opt.file));
+    let results = run_parallel(&seeds, opt.threads, |&seed| {
+        let mut agent = TwoStepSearchAgent::with_model(Box::new(model.clone()));
+        run_seeded_episode(&mut agent, seed, opt.lines, opt.max_steps)
+    });
+    let report = Report::new(&opt.file, opt.lines, opt.max_steps, &results);
+
+    println!(
+        "{} episodes in {:.1} s, {} truncated",
+        report.episodes.len(),
+        start.elapsed().as_secs_f64(),
+        report.truncated
+    );
+    let percentiles: Vec<String> =
+        PERCENTILES.iter().map(|p| format!("{:>9}", format!("p{}", p))).collect();
+    println!(
+        "{:14} {:>10} {:>24} {:>10} {:>24} {} {:>10}",
+        "", "mean", "95% CI", "median", "95% CI", percentiles.join(" "), "max"
+    );
+    for &name in METRICS.iter() {
+        let summary = &report.summary[name];
+        let percentiles: Vec<String> =
+            summary.percentiles.iter().map(|v| format!("{:9.3}", v)).collect();
+        println!(
+            "{:14} {:10.3} [{:10.3}, {:10.3}] {:10.3} [{:10.3}, {:10.3}] {} {:10.3}",
+            name,
+            summary.mean,
+            summary.ci95.0,
+            summary.ci95.1,
+            summary.median,
+            summary.median_ci95.0,
+            summary.median_ci95.1,
+            percentiles.join(" "),
+            summary.max
+        );
+    }
+
+    // truncated episodes are censored, so these estimate the lengths without the step limit
+    match report.median_survival {
+        Some(steps) => println!("Median survival (Kaplan-Meier): {} steps", steps),
+        None => println!("Median survival (Kaplan-Meier): beyond the step limit"),
+    }
+    if let Some(ref fit) = report.geometric {
+        println!(
+            "Death rate per piece: {:.3e}, expected length: {:.1} steps",
+            fit.death_rate, fit.expected_length
+        );
+    }
+
+    if let Some(ref file) = opt.json {
+        report.write_json(file);
+    }
+    if let Some(ref file) = opt.csv {
+        report.write_csv(file);
+    }
+}
@@ -0,0 +1,46 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use std::io::{self, BufRead, Write};
+use structopt::StructOpt;
+
+use tetris20g_ai::agent::TwoStepSearchAgent;
+use tetris20g_ai::bot::BotSession;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "bot",
+            about = "Run the AI as a bot speaking line-delimited JSON on stdin and stdout.")]
+struct Opt {
+    #[structopt(long = "file", help = "Weights file name.")]
+    file: String,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let mut session = BotSession::new(Box::new(TwoStepSearchAgent::new(&opt.file)));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let send = |out: &mut dyn Write, line: &str| {
+        writeln!(out, "{}", line).unwrap();
+        out.flush().unwrap();
+    };
+    send(&mut out, &BotSession::info().to_line());
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.unwrap();
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(reply) = session.handle_line(&line) {
+            send(&mut out, &reply);
+        }
+        if session.is_quit() {
+            break;
+        }
+    }
+}
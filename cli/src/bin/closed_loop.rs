@@ -1,3 +1,4 @@
+extern crate chrono;
 extern crate rand;
 extern crate structopt;
 #[macro_use]
@@ -5,13 +6,18 @@ extern crate structopt_derive;
 
 extern crate tetris20g_ai;
 
+use chrono::prelude::*;
+use std::fs;
 use std::io::{Write, stdout};
 use structopt::StructOpt;
 
 use tetris20g_ai::agent;
 use tetris20g_ai::agent::Agent;
-use tetris20g_ai::core;
+use tetris20g_ai::core::{self, Position};
 use cli::display::Display;
+use tetris20g_ai::enumeration::find_command_sequence;
+use tetris20g_ai::logger::SessionInfo;
+use tetris20g_ai::replay::ReplayWriter;
 use tetris20g_ai::utility;
 
 #[derive(StructOpt, Debug)]
@@ -26,10 +32,22 @@ struct Opt {
     #[structopt(long = "episodes", default_value = "1",
     help = "The number of episodes to calculate performance statistics.")]
     episodes: u32,
+
+    #[structopt(long = "replay-dir",
+    help = "Directory where replay files `episode-<n>.replay` are saved.")]
+    replay_dir: Option<String>,
+
+    #[structopt(long = "position",
+    help = "Position to start from instead of the empty field, e.g., `2TTT5/XXXXXX1XXX:TIO`. \
+The queue is followed by random pieces.")]
+    position: Option<Position>,
 }
 
 fn main() {
     let opt = Opt::from_args();
+    if let Some(ref position) = opt.position {
+        assert!(position.hold.is_none(), "hold is not supported");
+    }
 
     let display = if opt.auto { None } else { Some(Display::new()) };
     let mut scores = vec![];
@@ -37,8 +55,22 @@ fn main() {
     for episode in 1..(1 + opt.episodes) {
         let mut agent = agent::TwoStepSearchAgent::new(&opt.file);
 
-        let mut field = core::EMPTY_FIELD;
-        let seq = utility::generate_pieces(100000, Some(episode));
+        let session = SessionInfo {
+            seed: episode,
+            randomizer: String::from(utility::RANDOMIZER),
+            rules: core::Rules::default(),
+            lines: 0,
+            player: Some(format!("closed_loop:{}", opt.file)),
+            date: Local::now().to_rfc3339(),
+            input_profile: None,
+            position: opt.position.clone(),
+        };
+        let mut field = session.initial_field();
+        let seq = session.pieces(100000);
+        let mut replay = opt.replay_dir.as_ref().map(|dir| {
+            fs::create_dir_all(dir).unwrap();
+            ReplayWriter::create(&format!("{}/episode-{}.replay", dir, episode), &session)
+        });
 
         for step in 0.. {
             let next_piece = seq[step % seq.len()];
@@ -62,6 +94,9 @@ fn main() {
                 let _ = display.wait_key();
             }
 
+            if let Some(ref mut replay) = replay {
+                replay.write_piece(&find_command_sequence(&field, next_piece, &state));
+            }
             let (new_field, _) = core::fix_piece(&field, &state);
             field = new_field.clone();
         }
@@ -0,0 +1,113 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use std::sync::Arc;
+use structopt::StructOpt;
+
+use tetris20g_ai::agent::TwoStepSearchAgent;
+use tetris20g_ai::benchmark::{higher_is_better, parse_seeds, write_comparisons};
+use tetris20g_ai::benchmark::{Comparison, Report, METRICS};
+use tetris20g_ai::episode::{run_parallel, run_seeded_episode};
+use tetris20g_ai::regressor::{load_model, ValueModel};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "compare",
+            about = "Compare agents on the same seeded episodes with a paired significance test.")]
+struct Opt {
+    #[structopt(long = "file",
+                help = "Weights file name, given twice or more. The first one is the baseline.")]
+    files: Vec<String>,
+
+    #[structopt(long = "seeds", default_value = "1-100",
+                help = "Seeds of the episodes, e.g., `1-100,200,300-309`.")]
+    seeds: String,
+
+    #[structopt(long = "lines", default_value = "0",
+                help = "The number of lines initially filled at random.")]
+    lines: usize,
+
+    #[structopt(long = "max-steps",
+                help = "Episodes are truncated after this number of pieces.")]
+    max_steps: Option<usize>,
+
+    #[structopt(long = "threads", default_value = "4", help = "The number of worker threads.")]
+    threads: usize,
+
+    #[structopt(long = "metric", default_value = "steps",
+                help = "Metric compared, one of steps, lines, tetris_rate, max_height and \
+time_per_move. Smaller values of max_height and time_per_move are considered better.")]
+    metric: String,
+
+    #[structopt(long = "json", help = "Output file name for the comparisons in JSON.")]
+    json: Option<String>,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    assert!(opt.files.len() >= 2, "give at least two weights files by --file");
+    assert!(METRICS.contains(&opt.metric.as_str()), "unknown metric {}", opt.metric);
+    let seeds = parse_seeds(&opt.seeds).unwrap_or_else(|e| panic!("--seeds {}", e));
+    assert!(!seeds.is_empty(), "no seeds");
+
+    let tasks: Vec<(usize, u32)> = (0..opt.files.len())
+        .flat_map(|agent| seeds.iter().map(move |&seed| (agent, seed)))
+        .collect();
+    let models: Vec<Arc<dyn ValueModel>> =
+        opt.files.iter().map(|file| Arc::from(load_model(file))).collect();
+    let results = run_parallel(&tasks, opt.threads, |&(agent, seed)| {
+        let mut agent = TwoStepSearchAgent::with_model(Box::new(models[agent].clone()));
+        run_seeded_episode(&mut agent, seed, opt.lines, opt.max_steps)
+    });
+    let reports: Vec<Report> = opt
+        .files
+        .iter()
+        .zip(results.chunks(seeds.len()))
+        .map(|(file, results)| Report::new(file, opt.lines, opt.max_steps, results))
+        .collect();
+
+    let direction = if higher_is_better(&opt.metric) { "higher" } else { "lower" };
+    println!("{} episodes, metric {} ({} is better)", seeds.len(), opt.metric, direction);
+    for report in reports.iter() {
+        let summary = &report.summary[&opt.metric];
+        println!(
+            "  {}: mean {:.3} [{:.3}, {:.3}], median {:.3} [{:.3}, {:.3}], {} truncated",
+            report.agent,
+            summary.mean,
+            summary.ci95.0,
+            summary.ci95.1,
+            summary.median,
+            summary.median_ci95.0,
+            summary.median_ci95.1,
+            report.truncated
+        );
+    }
+
+    let comparisons: Vec<Comparison> = reports[1..]
+        .iter()
+        .map(|report| Comparison::new(&reports[0], report, &opt.metric))
+        .collect();
+    for comparison in comparisons.iter() {
+        let difference = &comparison.difference;
+        println!("{} vs {}:", comparison.candidate, comparison.baseline);
+        println!(
+            "  difference: mean {:+.3} [{:+.3}, {:+.3}], median {:+.3} [{:+.3}, {:+.3}]",
+            difference.mean,
+            difference.ci95.0,
+            difference.ci95.1,
+            difference.median,
+            difference.median_ci95.0,
+            difference.median_ci95.1
+        );
+        println!(
+            "  {} wins, {} losses, {} ties, Wilcoxon p = {:.4}",
+            comparison.wins, comparison.losses, comparison.ties, comparison.p_value
+        );
+    }
+
+    if let Some(ref file) = opt.json {
+        write_comparisons(&comparisons, file);
+    }
+}
@@ -6,30 +6,93 @@ extern crate tetris20g_ai;
 
 use structopt::StructOpt;
 
-use tetris20g_ai::dataset_generator::generate_dataset;
+use tetris20g_ai::dataset::DatasetKind;
+use tetris20g_ai::dataset_generator::{generate_dataset, DatasetOptions, Split, SplitBy, WriteMode};
+use tetris20g_ai::pair_sampler::parse_sampler;
 
 #[derive(StructOpt, Debug)]
-#[structopt(name = "dataset_generation", about = "Generate dataset from log file.")]
+#[structopt(name = "dataset_generation", about = "Generate dataset from log files.")]
 struct Opt {
     #[structopt(long = "input",
-                help = "Input log file name. (e.g., `dataset/20171101-000000.txt`)")]
-    input: String,
+                help = "Input log file names. Can be specified multiple times. \
+(e.g., `dataset/20171101-000000.txt`)")]
+    input: Vec<String>,
 
-    #[structopt(long = "output", default_value = "output.bin", help = " Output binary file name.")]
+    #[structopt(long = "output", default_value = "output",
+                help = "Output path prefix. Shards are written to `<prefix>-<split>-<index>.bin` \
+and the manifest to `<prefix>.manifest.json`.")]
     output: String,
 
-    #[structopt(long = "drop-rate", default_value = "0",
-                help = "Rate for drop. This should be between 0.0 and 1.0.")]
-    drop_rate: f64,
+    #[structopt(long = "shard-size", default_value = "1073741824",
+                help = "Maximum size of a shard in bytes.")]
+    shard_size: usize,
 
-    #[structopt(long = "weights-file",
-                help = "If not specified, drop is uniformly done for all candidates at random. \
-Otherwise, weights file is loaded and bottom `drop_rate` of candidates in terms of value scores \
-are dropped.")]
+    #[structopt(long = "val-ratio", default_value = "0",
+                help = "Ratio of data put into the validation split.")]
+    val_ratio: f64,
+
+    #[structopt(long = "split-by", default_value = "game",
+                help = "Unit of the train/validation split: `game` or `step`.")]
+    split_by: SplitBy,
+
+    #[structopt(long = "mode", default_value = "fail",
+                help = "What to do if the output exists: `fail`, `overwrite` or `append`.")]
+    mode: WriteMode,
+
+    #[structopt(long = "kind", default_value = "pairwise",
+                help = "Record kind: `pairwise` writes the human choice paired with each \
+alternative, `listwise` writes all candidates with the index of the human choice.")]
+    kind: DatasetKind,
+
+    #[structopt(long = "horizon", default_value = "2",
+                help = "The number of pieces placed before fields are compared.")]
+    horizon: usize,
+
+    #[structopt(long = "sampler", default_value = "uniform:1",
+                help = "Strategy for selecting alternatives: `uniform:<keep rate>`, \
+`top-ratio:<ratio>`, `top-k:<k>`, `margin:<value margin>` or `stratified:<per height>`. \
+All but `uniform` and `stratified` require `--weights-file`.")]
+    sampler: String,
+
+    #[structopt(long = "weights-file", help = "Weights file for value scores of alternatives.")]
     weights_file: Option<String>,
+
+    #[structopt(long = "seed", default_value = "0", help = "Seed for random selection.")]
+    seed: u32,
+
+    #[structopt(long = "metadata",
+                help = "Store source log, step and rank of the alternative for each record.")]
+    metadata: bool,
 }
 
 fn main() {
     let opt = Opt::from_args();
-    generate_dataset(&opt.input, &opt.output, opt.drop_rate, opt.weights_file);
+    let options = DatasetOptions {
+        inputs: opt.input,
+        output: opt.output,
+        max_shard_bytes: opt.shard_size,
+        validation_ratio: opt.val_ratio,
+        split_by: opt.split_by,
+        write_mode: opt.mode,
+        kind: opt.kind,
+        horizon: opt.horizon,
+        sampler: parse_sampler(&opt.sampler).unwrap_or_else(|e| panic!("{}", e)),
+        weights_file: opt.weights_file,
+        seed: opt.seed,
+        metadata: opt.metadata,
+    };
+    let manifest = generate_dataset(&options);
+    for shard in manifest.shards.iter() {
+        println!("{}: {} records", shard.file, shard.records);
+    }
+    let summary = manifest.sampling.last().unwrap();
+    println!("Sampler: {}, {} steps, {} pairs", summary.strategy, summary.steps(), summary.pairs());
+    for (&pairs, &steps) in summary.histogram.iter() {
+        println!("  {} steps contributed {} pairs", steps, pairs);
+    }
+    println!(
+        "Train: {} records, Validation: {} records",
+        manifest.records(Split::Train),
+        manifest.records(Split::Validation)
+    );
 }
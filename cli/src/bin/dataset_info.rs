@@ -0,0 +1,78 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use std::process;
+use structopt::StructOpt;
+
+use tetris20g_ai::core::EMPTY_FIELD;
+use tetris20g_ai::dataset::{DatasetKind, DatasetReader};
+use tetris20g_ai::regressor::{extract_feature, FEATURE_EXTRACTOR_ID};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "dataset_info", about = "Validate and inspect dataset files.")]
+struct Opt {
+    #[structopt(help = "Dataset file names.")]
+    files: Vec<String>,
+
+    #[structopt(long = "show", default_value = "0",
+                help = "The number of records whose metadata and active feature counts are shown.")]
+    show: usize,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let dim = extract_feature(&EMPTY_FIELD).len() as u32;
+    let mut failed = false;
+
+    for file in opt.files.iter() {
+        let mut reader = match DatasetReader::open(file) {
+            Ok(reader) => reader,
+            Err(err) => {
+                println!("{}: invalid ({})", file, err);
+                failed = true;
+                continue;
+            }
+        };
+        let header = reader.header().clone();
+        println!(
+            "{}: version {}, {:?}, feature extractor {}, dimension {}, {} records, metadata: {}",
+            file, header.version, header.kind, header.feature_extractor, header.dimension,
+            header.records, header.has_metadata
+        );
+        if header.feature_extractor != FEATURE_EXTRACTOR_ID || header.dimension != dim {
+            println!(
+                "  warning: this build uses feature extractor {} with dimension {}",
+                FEATURE_EXTRACTOR_ID, dim
+            );
+        }
+        if header.has_metadata {
+            println!("  sources: {}", reader.sources().join(", "));
+        }
+        for idx in 0..(opt.show as u64).min(header.records) {
+            let count = |f: &Vec<bool>| f.iter().filter(|&&b| b).count();
+            let counts = match header.kind {
+                DatasetKind::Pairwise => {
+                    let (feature0, feature1) = reader.pair(idx).unwrap();
+                    format!("{} vs {}", count(&feature0), count(&feature1))
+                }
+                DatasetKind::Listwise => {
+                    let (features, choice) = reader.list(idx).unwrap();
+                    let counts: Vec<String> = features.iter().map(|f| count(f).to_string()).collect();
+                    format!("[{}], choice {}", counts.join(", "), choice)
+                }
+            };
+            let metadata = match reader.metadata(idx) {
+                Some(m) => format!("source: {}, step: {}, rank: {}", m.source, m.step, m.rank),
+                None => String::from("no metadata"),
+            };
+            println!("  #{}: active features {}, {}", idx, counts, metadata);
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
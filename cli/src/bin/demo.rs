@@ -1,3 +1,4 @@
+extern crate chrono;
 extern crate rand;
 extern crate structopt;
 #[macro_use]
@@ -5,13 +6,17 @@ extern crate structopt_derive;
 
 extern crate tetris20g_ai;
 
+use chrono::prelude::*;
+use std::fs;
 use structopt::StructOpt;
 
 use tetris20g_ai::agent;
 use tetris20g_ai::agent::Agent;
-use tetris20g_ai::core;
+use tetris20g_ai::core::{self, Position};
 use cli::display::Display;
 use tetris20g_ai::enumeration::find_command_sequence;
+use tetris20g_ai::logger::SessionInfo;
+use tetris20g_ai::replay::ReplayWriter;
 use tetris20g_ai::utility;
 
 #[derive(StructOpt, Debug)]
@@ -24,18 +29,45 @@ struct Opt {
     #[structopt(long = "lines", default_value = "8",
     help = "The number of lines initially filled at random.")]
     lines: usize,
+
+    #[structopt(long = "replay-dir", help = "Directory where replay files of games are saved.")]
+    replay_dir: Option<String>,
+
+    #[structopt(long = "position",
+    help = "Position to start from instead of the filled lines, e.g., `2TTT5/XXXXXX1XXX:TIO`. \
+The queue is followed by random pieces.")]
+    position: Option<Position>,
 }
 
 fn main() {
     let opt = Opt::from_args();
+    if let Some(ref position) = opt.position {
+        assert!(position.hold.is_none(), "hold is not supported");
+    }
 
     let display = Display::new();
 
     loop {
         let mut agent = agent::TwoStepSearchAgent::new(&opt.file);
 
-        let mut field = utility::filled_field(opt.lines, None);
-        let seq = utility::generate_pieces(100000, None);
+        let seed = utility::random_seed();
+        let session = SessionInfo {
+            seed,
+            randomizer: String::from(utility::RANDOMIZER),
+            rules: core::Rules::default(),
+            lines: opt.lines,
+            player: Some(format!("demo:{}", opt.file)),
+            date: Local::now().to_rfc3339(),
+            input_profile: None,
+            position: opt.position.clone(),
+        };
+        let mut field = session.initial_field();
+        let seq = session.pieces(100000);
+        let mut replay = opt.replay_dir.as_ref().map(|dir| {
+            fs::create_dir_all(dir).unwrap();
+            let name = Local::now().format("%Y%m%d-%H%M%S");
+            ReplayWriter::create(&format!("{}/{}-{}.replay", dir, name, seed), &session)
+        });
         let mut score_info = core::ScoreInfo::new();
 
         for step in 0.. {
@@ -49,6 +81,9 @@ fn main() {
             };
 
             let seq = find_command_sequence(&field, next_piece, &dest_state);
+            if let Some(ref mut replay) = replay {
+                replay.write_piece(&seq);
+            }
             let mut state = core::new_piece(next_piece);
             for command in seq {
                 display.erase();
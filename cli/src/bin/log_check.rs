@@ -0,0 +1,129 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use std::fs;
+use std::path::Path;
+use std::process;
+use structopt::StructOpt;
+
+use tetris20g_ai::dataset_generator::Manifest;
+use tetris20g_ai::log_check::{LogChecker, LogReport};
+use tetris20g_ai::logger::{read_log, Logger};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "log_check", about = "Validate log files and show their statistics.")]
+struct Opt {
+    #[structopt(help = "Log file names.")]
+    files: Vec<String>,
+
+    #[structopt(long = "output-dir",
+                help = "Directory where cleaned logs are written with the same file names.")]
+    output_dir: Option<String>,
+
+    #[structopt(long = "max-issues", default_value = "20",
+                help = "The maximum number of issues shown for each file.")]
+    max_issues: usize,
+
+    #[structopt(long = "manifest",
+                help = "Dataset manifest whose shards are also validated.")]
+    manifest: Option<String>,
+}
+
+fn check_file(file: &str, opt: &Opt) -> LogReport {
+    let (header, entries) = read_log(file);
+    let mut logger = opt.output_dir.as_ref().map(|dir| {
+        fs::create_dir_all(dir).unwrap();
+        let name = Path::new(file).file_name().unwrap();
+        let output = Path::new(dir).join(name);
+        let output = output.to_str().unwrap();
+        match header {
+            Some(ref header) => Logger::new(output, &header.session),
+            None => Logger::without_header(output),
+        }
+    });
+
+    let mut checker = LogChecker::new();
+    for entry in entries {
+        if let Some(cleaned) = checker.check(entry) {
+            if let Some(ref mut logger) = logger {
+                logger.save(&cleaned);
+            }
+        }
+    }
+    let report = checker.report;
+
+    match header {
+        Some(ref header) => println!(
+            "{}: version {}, seed {}, player {}",
+            file,
+            header.version,
+            header.session.seed,
+            header.session.player.as_ref().map_or("unknown", |p| p.as_str())
+        ),
+        None => println!("{}: version 1", file),
+    }
+    println!(
+        "  {} entries, {} kept in {} segments, {} issues",
+        report.entries, report.kept, report.segments, report.issues.len()
+    );
+    for issue in report.issues.iter().take(opt.max_issues) {
+        println!("  {}", issue);
+    }
+    if report.issues.len() > opt.max_issues {
+        println!("  ... and {} more", report.issues.len() - opt.max_issues);
+    }
+    report
+}
+
+fn show_statistics(report: &LogReport) {
+    let counts = report.issue_counts();
+    println!(
+        "Total: {} entries, {} kept in {} segments",
+        report.entries, report.kept, report.segments
+    );
+    println!(
+        "Issues: {} unreachable, {} discontinuities, {} inconsistent, {} next piece mismatches",
+        counts[0], counts[1], counts[2], counts[3]
+    );
+
+    println!("Pieces:");
+    for (&piece, &count) in report.pieces.iter() {
+        let ratio = count as f64 / report.entries as f64;
+        println!("  {}: {:6} ({:5.1}%)", piece as char, count, ratio * 100.0);
+    }
+
+    println!("Stack heights:");
+    let max = report.heights.values().cloned().max().unwrap_or(1);
+    for (&height, &count) in report.heights.iter() {
+        let bar = "#".repeat((count * 40).div_ceil(max));
+        println!("  {:2}: {:6} {}", height, count, bar);
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    let mut total = LogReport::default();
+    for file in opt.files.iter() {
+        total.merge(&check_file(file, &opt));
+    }
+    if total.entries > 0 {
+        show_statistics(&total);
+    }
+
+    if let Some(ref manifest_file) = opt.manifest {
+        let manifest = Manifest::load(manifest_file);
+        let dir = Path::new(manifest_file).parent().unwrap();
+        let problems = manifest.verify(dir);
+        println!("{}: {} shards, {} problems", manifest_file, manifest.shards.len(), problems.len());
+        for problem in problems.iter() {
+            println!("  {}", problem);
+        }
+        if !problems.is_empty() {
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use std::path::Path;
+use structopt::StructOpt;
+
+use tetris20g_ai::agent::TwoStepSearchAgent;
+use tetris20g_ai::episode::run_seeded_episode;
+use tetris20g_ai::optimizer::{run_generation, CemConfig, CemState};
+use tetris20g_ai::regressor::{save_model, LinearRegressor};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "optimize",
+            about = "Optimize weights by the cross-entropy method on closed loop play.")]
+struct Opt {
+    #[structopt(long = "init-file",
+                help = "Initial weights file name. If not specified, weights start from zero.")]
+    init_file: Option<String>,
+
+    #[structopt(long = "checkpoint", default_value = "cem_checkpoint.json",
+                help = "Checkpoint file name. If it exists, optimization is resumed from it, which \
+requires the same population, elite ratio, noise, episodes, max steps, lines and seed.")]
+    checkpoint: String,
+
+    #[structopt(long = "output", default_value = "cem_weights.txt",
+                help = "Output file name for the best weights so far.")]
+    output: String,
+
+    #[structopt(long = "generations", default_value = "100",
+                help = "The total number of generations.")]
+    generations: usize,
+
+    #[structopt(long = "population", default_value = "32",
+                help = "The number of samples in a generation.")]
+    population: usize,
+
+    #[structopt(long = "elite-ratio", default_value = "0.25",
+                help = "The ratio of samples used for updating the distribution.")]
+    elite_ratio: f64,
+
+    #[structopt(long = "stdev", default_value = "0.05",
+                help = "Initial standard deviation of the sampling distribution.")]
+    stdev: f32,
+
+    #[structopt(long = "noise", default_value = "0.005",
+                help = "Standard deviation added after every update.")]
+    noise: f32,
+
+    #[structopt(long = "episodes", default_value = "4",
+                help = "The number of episodes for evaluating a sample.")]
+    episodes: usize,
+
+    #[structopt(long = "max-steps", default_value = "1000",
+                help = "Episodes are truncated after this number of pieces.")]
+    max_steps: usize,
+
+    #[structopt(long = "lines", default_value = "0",
+                help = "The number of lines initially filled at random.")]
+    lines: usize,
+
+    #[structopt(long = "threads", default_value = "4", help = "The number of worker threads.")]
+    threads: usize,
+
+    #[structopt(long = "seed", default_value = "0", help = "Seed for sampling and episodes.")]
+    seed: u32,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let config = CemConfig {
+        population: opt.population,
+        elite_ratio: opt.elite_ratio,
+        noise: opt.noise,
+        episodes: opt.episodes,
+        seed: opt.seed,
+        max_steps: opt.max_steps,
+        lines: opt.lines,
+    };
+
+    let mut state = if Path::new(&opt.checkpoint).exists() {
+        let mut state = CemState::load(&opt.checkpoint);
+        state
+            .resume_with(&config)
+            .unwrap_or_else(|e| panic!("{}: {}", opt.checkpoint, e));
+        println!("Resumed from generation {}", state.generation);
+        state
+    } else {
+        let mut regressor = LinearRegressor::new();
+        if let Some(ref file) = opt.init_file {
+            regressor.load(file);
+        }
+        CemState::new(regressor.params().to_vec(), opt.stdev, &config)
+    };
+
+    while state.generation < opt.generations {
+        let summary = run_generation(&mut state, &config, opt.threads, |params, seed| {
+            let regressor = LinearRegressor::from_params(params.to_vec());
+            let mut agent = TwoStepSearchAgent::with_model(Box::new(regressor));
+            let result = run_seeded_episode(&mut agent, seed, config.lines, Some(config.max_steps));
+            result.score_info.steps as f64
+        });
+        println!(
+            "Generation: {}, Best: {}, Elite: {}, Mean: {}",
+            summary.generation,
+            summary.best_fitness,
+            summary.elite_fitness,
+            summary.mean_fitness
+        );
+
+        save_model(&LinearRegressor::from_params(state.best_params.clone()), &opt.output);
+        state.save(&opt.checkpoint);
+    }
+}
@@ -0,0 +1,108 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use structopt::StructOpt;
+
+use cli::display::Display;
+use tetris20g_ai::fumen;
+use tetris20g_ai::replay::{Frame, Replay};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "replay", about = "Play a replay file saved by demo, closed_loop or annotation.")]
+struct Opt {
+    #[structopt(help = "Replay file name.")]
+    file: String,
+
+    #[structopt(long = "interval", default_value = "100",
+    help = "Milliseconds per command.")]
+    interval: i32,
+
+    #[structopt(long = "start", default_value = "0",
+    help = "Index of the piece the replay starts from.")]
+    start: usize,
+
+    #[structopt(long = "fumen", help = "Print the placements as a fumen instead of playing.")]
+    fumen: bool,
+}
+
+/// Returns the index of the first frame of a piece, or the last frame if there is no such piece.
+fn seek(frames: &[Frame], piece: usize) -> usize {
+    frames
+        .iter()
+        .position(|frame| frame.piece >= piece)
+        .unwrap_or(frames.len() - 1)
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    let replay = Replay::load(&opt.file);
+    if opt.fumen {
+        let fumen = fumen::encode_replay(&replay).unwrap_or_else(|e| panic!("{}: {}", opt.file, e));
+        println!("{}", fumen);
+        return;
+    }
+    let frames = replay.frames().unwrap_or_else(|e| panic!("{}: {}", opt.file, e));
+    let last = frames.len() - 1;
+    let pieces = frames[last].piece;
+
+    let mut pos = seek(&frames, opt.start);
+    let mut interval = opt.interval.max(1);
+    let mut paused = false;
+    let mut seek_input = String::new();
+
+    let display = Display::new();
+    loop {
+        let frame = &frames[pos];
+        display.erase();
+        display.draw_field(&frame.field, &frame.state, Some(frame.next_piece));
+        display.draw_score_info(&frame.score_info);
+        display.draw_text(18, &format!("Piece: {}/{}", frame.piece, pieces));
+        let status = if paused { String::from("Paused") } else { format!("{} ms", interval) };
+        display.draw_text(19, &status);
+        if !seek_input.is_empty() {
+            display.draw_text(20, &format!("Go to: {}", seek_input));
+        }
+        display.draw_text(22, "space:pause q:quit");
+        display.draw_text(23, ",/.:step [/]:piece");
+        display.draw_text(24, "+/-:speed <n>g:seek");
+        display.refresh();
+
+        let key = if paused { display.wait_key() } else { display.poll_key(interval) };
+        match key {
+            Some(' ') => paused = !paused,
+            Some('.') => {
+                paused = true;
+                pos = (pos + 1).min(last);
+            }
+            Some(',') => {
+                paused = true;
+                pos = pos.saturating_sub(1);
+            }
+            Some(']') => pos = seek(&frames, frame.piece + 1),
+            Some('[') => pos = seek(&frames, frame.piece.saturating_sub(1)),
+            Some('+') => interval = (interval / 2).max(1),
+            Some('-') => interval = (interval * 2).min(5000),
+            Some(c) if c.is_ascii_digit() => seek_input.push(c),
+            Some('g') | Some('\n') => {
+                if let Ok(piece) = seek_input.parse() {
+                    pos = seek(&frames, piece);
+                    paused = true;
+                }
+                seek_input.clear();
+            }
+            Some('q') => break,
+            None if !paused => {
+                if pos < last {
+                    pos += 1;
+                } else {
+                    paused = true;
+                }
+            }
+            _ => (),
+        }
+    }
+}
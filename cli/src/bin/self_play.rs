@@ -0,0 +1,111 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use structopt::StructOpt;
+
+use tetris20g_ai::regressor::{
+    load_trainable_model, save_model, LinearRegressor, MlpRegressor, TrainableModel,
+};
+use tetris20g_ai::self_play::{run_iteration, SelfPlayConfig, TargetKind};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "self_play", about = "Learn a value model by TD targets from self-play.")]
+struct Opt {
+    #[structopt(long = "init-file",
+                help = "Initial model file name. If not specified, a new model is created.")]
+    init_file: Option<String>,
+
+    #[structopt(long = "hidden", default_value = "0",
+                help = "Hidden units of a newly created MLP model. 0 means a linear model.")]
+    hidden: usize,
+
+    #[structopt(long = "output", default_value = "self_play_weights.txt",
+                help = "Output model file name. It is saved after every iteration.")]
+    output: String,
+
+    #[structopt(long = "iterations", default_value = "100", help = "The number of iterations.")]
+    iterations: usize,
+
+    #[structopt(long = "start-iteration", default_value = "0",
+                help = "Index of the first iteration. Use this with `--init-file` to resume.")]
+    start_iteration: usize,
+
+    #[structopt(long = "episodes", default_value = "8",
+                help = "The number of self-play episodes in an iteration.")]
+    episodes: usize,
+
+    #[structopt(long = "max-steps", default_value = "500",
+                help = "Episodes are truncated after this number of pieces.")]
+    max_steps: usize,
+
+    #[structopt(long = "lines", default_value = "0",
+                help = "The number of lines initially filled at random.")]
+    lines: usize,
+
+    #[structopt(long = "epsilon", default_value = "0.05",
+                help = "Probability of a random placement for exploration.")]
+    epsilon: f64,
+
+    #[structopt(long = "gamma", default_value = "0.99", help = "Discount factor.")]
+    gamma: f32,
+
+    #[structopt(long = "lambda", default_value = "0.8",
+                help = "Lambda of TD(lambda) targets. Ignored if `--n-step` is specified.")]
+    lambda: f32,
+
+    #[structopt(long = "n-step", help = "Use n-step returns instead of TD(lambda).")]
+    n_step: Option<usize>,
+
+    #[structopt(long = "lr", default_value = "0.01", help = "Learning rate.")]
+    lr: f32,
+
+    #[structopt(long = "epochs", default_value = "1",
+                help = "The number of passes over samples in an iteration.")]
+    epochs: usize,
+
+    #[structopt(long = "threads", default_value = "4", help = "The number of worker threads.")]
+    threads: usize,
+
+    #[structopt(long = "seed", default_value = "0", help = "Seed for episodes and shuffling.")]
+    seed: u32,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let config = SelfPlayConfig {
+        episodes: opt.episodes,
+        max_steps: opt.max_steps,
+        lines: opt.lines,
+        epsilon: opt.epsilon,
+        gamma: opt.gamma,
+        target: match opt.n_step {
+            Some(n) => TargetKind::NStep(n),
+            None => TargetKind::Lambda(opt.lambda),
+        },
+        learning_rate: opt.lr,
+        epochs: opt.epochs,
+        threads: opt.threads,
+        seed: opt.seed,
+    };
+
+    let mut model: Box<dyn TrainableModel> = match opt.init_file {
+        Some(ref file) => load_trainable_model(file),
+        None if opt.hidden > 0 => Box::new(MlpRegressor::new(opt.hidden, opt.seed)),
+        None => Box::new(LinearRegressor::new()),
+    };
+
+    for iteration in opt.start_iteration..opt.iterations {
+        let summary = run_iteration(model.as_mut(), &config, iteration);
+        println!(
+            "Iteration: {}, Average steps: {}, Samples: {}, MSE: {}",
+            iteration,
+            summary.average_steps,
+            summary.samples,
+            summary.mean_squared_error
+        );
+        save_model(model.as_ref(), &opt.output);
+    }
+}
@@ -0,0 +1,32 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use std::net::TcpListener;
+use std::sync::Arc;
+use structopt::StructOpt;
+
+use tetris20g_ai::api::serve;
+use tetris20g_ai::regressor::{load_model, ValueModel};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "server",
+            about = "Serve the engine by a JSON lines API over TCP. See `tetris20g_ai::api`.")]
+struct Opt {
+    #[structopt(long = "file", help = "Weights file name.")]
+    file: String,
+
+    #[structopt(long = "address", default_value = "127.0.0.1:7620",
+                help = "Address to listen on.")]
+    address: String,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let model: Arc<dyn ValueModel> = Arc::from(load_model(&opt.file));
+    let listener = TcpListener::bind(&opt.address).unwrap();
+    println!("Listening on {}", listener.local_addr().unwrap());
+    serve(listener, model);
+}
@@ -0,0 +1,128 @@
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+extern crate tetris20g_ai;
+
+use std::fs;
+use structopt::StructOpt;
+
+use tetris20g_ai::core::{HEIGHT, WIDTH};
+use tetris20g_ai::regressor::{feature_layout, Polarity, MAX_DI, MAX_DJ};
+use tetris20g_ai::weight_map::{color, weight_maps, write_ppm, write_svg, WeightMap};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "weights_inspect",
+            about = "Decode weights of the linear model into heatmaps by offset and polarity.")]
+struct Opt {
+    #[structopt(long = "file", help = "Weights file name.")]
+    file: String,
+
+    #[structopt(long = "diff",
+                help = "Baseline weights file name. The difference from it is inspected.")]
+    diff: Option<String>,
+
+    #[structopt(long = "ppm", help = "Output file name of a PPM image.")]
+    ppm: Option<String>,
+
+    #[structopt(long = "svg", help = "Output file name of an SVG image.")]
+    svg: Option<String>,
+
+    #[structopt(long = "scale", default_value = "8", help = "Pixels per cell in images.")]
+    scale: usize,
+
+    #[structopt(long = "top", default_value = "10",
+                help = "The number of the largest weights listed.")]
+    top: usize,
+}
+
+fn read_weights(filename: &str) -> Vec<f32> {
+    let text = fs::read_to_string(filename).unwrap();
+    text.split_whitespace()
+        .map(|x| x.parse::<f32>())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|_| panic!("{}: not weights of the linear model", filename))
+}
+
+/// Prints maps of a polarity in the terminal with 24-bit colors, a row of maps for each `di`.
+fn print_maps(maps: &[WeightMap], polarity: Polarity, max: f32) {
+    for di in 0..MAX_DI {
+        let row: Vec<Option<&WeightMap>> = (-MAX_DJ..MAX_DJ + 1)
+            .map(|dj| maps.iter().find(|m| (m.di, m.dj, m.polarity) == (di, dj, polarity)))
+            .collect();
+        let labels: Vec<String> = (-MAX_DJ..MAX_DJ + 1)
+            .map(|dj| format!("{:<width$}", format!("{},{}", di, dj), width = WIDTH + 2))
+            .collect();
+        println!("{}", labels.concat());
+        for i in 0..HEIGHT {
+            let mut line = String::new();
+            for map in row.iter() {
+                for j in 0..WIDTH {
+                    let (r, g, b) = color(map.and_then(|m| m.values[i][j]), max);
+                    line += &format!("\x1b[48;2;{};{};{}m ", r, g, b);
+                }
+                line += "\x1b[0m  ";
+            }
+            println!("{}", line);
+        }
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let mut params = read_weights(&opt.file);
+    if let Some(ref baseline) = opt.diff {
+        let baseline = read_weights(baseline);
+        assert_eq!(params.len(), baseline.len(), "the numbers of weights differ");
+        for (w, b) in params.iter_mut().zip(baseline.iter()) {
+            *w -= b;
+        }
+    }
+    let maps = weight_maps(&params).unwrap_or_else(|e| panic!("{}: {}", opt.file, e));
+
+    let finite: Vec<f32> = params.iter().cloned().filter(|w| w.is_finite()).collect();
+    let mean = finite.iter().sum::<f32>() / finite.len() as f32;
+    println!(
+        "{} weights{}: {} not finite, {} zero, min {:.4}, mean {:.4}, max {:.4}",
+        params.len(),
+        if opt.diff.is_some() { " (difference)" } else { "" },
+        params.len() - finite.len(),
+        params.iter().filter(|&&w| w == 0.0).count(),
+        finite.iter().cloned().fold(f32::INFINITY, f32::min),
+        mean,
+        finite.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+    );
+
+    println!("{:<26} {:>9} {:>9} {:>9}", "map", "min", "mean", "max");
+    for map in maps.iter() {
+        let (min, mean, max) = map.summary();
+        println!("{:<26} {:>9.4} {:>9.4} {:>9.4}", map.label(), min, mean, max);
+    }
+
+    let layout = feature_layout();
+    let mut order: Vec<usize> = (0..params.len()).collect();
+    // NaN sorts first, since it is greater than any number by `total_cmp`
+    order.sort_by(|&a, &b| params[b].abs().total_cmp(&params[a].abs()));
+    println!("Largest weights:");
+    for &k in order.iter().take(opt.top) {
+        let f = &layout[k];
+        println!(
+            "  ({:2}, {}) di={} dj={:2} {:?}: {:.4}",
+            f.i, f.j, f.di, f.dj, f.polarity, params[k]
+        );
+    }
+
+    if let Some(ref file) = opt.ppm {
+        write_ppm(&maps, opt.scale, file);
+    }
+    if let Some(ref file) = opt.svg {
+        write_svg(&maps, opt.scale, file);
+    }
+    if opt.ppm.is_none() && opt.svg.is_none() {
+        let max = maps.iter().fold(0.0, |max: f32, m| max.max(m.max_abs()));
+        for &polarity in [Polarity::EmptyAboveFilled, Polarity::FilledAboveEmpty].iter() {
+            println!("{:?} by (di,dj):", polarity);
+            print_maps(&maps, polarity, max);
+        }
+    }
+}
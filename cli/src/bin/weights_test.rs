@@ -7,10 +7,10 @@ extern crate tetris20g_ai;
 
 use structopt::StructOpt;
 
-use tetris20g_ai::core;
+use tetris20g_ai::core::{self, Position};
 use cli::display::Display;
 use tetris20g_ai::enumeration::enumerate_multi;
-use tetris20g_ai::regressor::LinearRegressor;
+use tetris20g_ai::regressor::load_model;
 use tetris20g_ai::utility;
 
 #[derive(StructOpt, Debug)]
@@ -18,15 +18,25 @@ use tetris20g_ai::utility;
 struct Opt {
     #[structopt(long = "file", help = "Weights file name.")]
     file: String,
+
+    #[structopt(long = "position",
+    help = "Position whose field and first two pieces are tested instead of filled lines and L, S, \
+e.g., `2TTT5/XXXXXX1XXX:TI`.")]
+    position: Option<Position>,
 }
 
 fn main() {
     let opt = Opt::from_args();
-    let mut regressor = LinearRegressor::new();
-    regressor.load(&opt.file);
-
-    let field = utility::filled_field(9, None);
-    let candidates = enumerate_multi(&field, &vec![b'L', b'S']);
+    let regressor = load_model(&opt.file);
+
+    let (field, pieces) = match opt.position {
+        Some(ref position) => {
+            assert!(position.queue.len() >= 2, "the queue of --position needs two pieces");
+            (position.field, position.queue[..2].to_vec())
+        }
+        None => (utility::filled_field(9, None), vec![b'L', b'S']),
+    };
+    let candidates = enumerate_multi(&field, &pieces);
     let display = Display::new();
 
     let mut sorted: Vec<(f32, core::Field)> = candidates
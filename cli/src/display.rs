@@ -5,6 +5,12 @@ extern crate pancurses;
 use tetris20g_ai::core;
 use tetris20g_ai::core::{Field, PieceState};
 
+/// The number of color levels of each sign in heatmaps.
+const HEATMAP_LEVELS: i16 = 4;
+/// Base ids of the colors and color pairs of heatmaps.
+const HEATMAP_NEGATIVE: i16 = 16;
+const HEATMAP_POSITIVE: i16 = HEATMAP_NEGATIVE + HEATMAP_LEVELS;
+
 pub struct Display {
     window: pancurses::Window,
 }
@@ -36,6 +42,13 @@ impl Display {
         for c in "IOSZLJTX".bytes() {
             pancurses::init_pair(c as i16, 3, c as i16);
         }
+        for level in 1..=HEATMAP_LEVELS {
+            let intensity = 1000 * level / HEATMAP_LEVELS;
+            pancurses::init_color(HEATMAP_NEGATIVE + level, intensity, 100, 100);
+            pancurses::init_color(HEATMAP_POSITIVE + level, 100, intensity, 100);
+            pancurses::init_pair(HEATMAP_NEGATIVE + level, 3, HEATMAP_NEGATIVE + level);
+            pancurses::init_pair(HEATMAP_POSITIVE + level, 3, HEATMAP_POSITIVE + level);
+        }
         pancurses::init_pair(b'.' as i16, 2, 1);
         pancurses::init_pair(b'{' as i16, 3, 1);
 
@@ -93,6 +106,57 @@ impl Display {
         }
     }
 
+    /// Draws a placement suggestion on empty cells which the current piece does not cover.
+    pub fn draw_ghost(&self, field: &Field, ghost: &PieceState, current: &PieceState) {
+        let x_offset = 2;
+        let y_offset = 5;
+        let current_cells = cells(current);
+        for (y, x) in cells(ghost) {
+            if y < 0 || field[y as usize][x as usize] != b'.' || current_cells.contains(&(y, x)) {
+                continue;
+            }
+            self.window.mv(y_offset + y, x_offset + x);
+            self.window.attrset(pancurses::COLOR_PAIR(b'{' as u32));
+            self.window.addch('+');
+        }
+    }
+
+    /// Draws the field colored by values of cells, red for negative and green for positive ones,
+    /// e.g., `Explanation::by_cell`. Colors are scaled by the maximum absolute value.
+    pub fn draw_heatmap(&self, field: &Field, values: &[Vec<f32>]) {
+        let x_offset = 2;
+        let y_offset = 5;
+        let max = values.iter().flatten().fold(0.0f32, |max, v| max.max(v.abs()));
+        for (i, row) in field.iter().enumerate() {
+            for (j, &cell) in row.iter().enumerate() {
+                let value = values[i][j];
+                let level = if max > 0.0 {
+                    (value.abs() / max * HEATMAP_LEVELS as f32).ceil() as i16
+                } else {
+                    0
+                };
+                let pair = match level {
+                    0 => b'.' as i16,
+                    _ if value < 0.0 => HEATMAP_NEGATIVE + level,
+                    _ => HEATMAP_POSITIVE + level,
+                };
+                self.window.mv(y_offset + i as i32, x_offset + j as i32);
+                self.window.attrset(pancurses::COLOR_PAIR(pair as u32));
+                self.window.addch(if cell == b'.' { '.' } else { '#' });
+            }
+        }
+    }
+
+    /// Highlights a cell of the field, e.g., as the cursor of an editor.
+    pub fn draw_cursor(&self, field: &Field, y: usize, x: usize) {
+        let x_offset = 2;
+        let y_offset = 5;
+        self.window.mv(y_offset + y as i32, x_offset + x as i32);
+        self.window.attrset(pancurses::COLOR_PAIR(b'{' as u32) | pancurses::A_REVERSE);
+        self.window.addch(if field[y][x] == b'.' { '_' } else { '@' });
+        self.window.attroff(pancurses::A_REVERSE);
+    }
+
     pub fn refresh(&self) {
         // refresh the window
         self.window.refresh();
@@ -122,6 +186,21 @@ impl Display {
         self.window.addstr(format!("Steps: {:4}", score_info.steps).as_str());
     }
 
+    /// Draws a line of text on the right side of the field. `row` is counted from the top.
+    pub fn draw_text(&self, row: i32, text: &str) {
+        self.window.attrset(pancurses::COLOR_PAIR(b'{' as u32));
+        self.window.mv(row, (core::WIDTH + 4) as i32);
+        self.window.addstr(text);
+    }
+
+    /// Waits for a key at most `ms` milliseconds. Returns None if no key is pressed.
+    pub fn poll_key(&self, ms: i32) -> Option<char> {
+        self.window.timeout(ms);
+        let key = self.wait_key();
+        self.window.timeout(-1);
+        key
+    }
+
     pub fn wait_key(&self) -> Option<char> {
         match self.window.getch() {
             Some(pancurses::Input::Character(c)) => Some(c),
@@ -129,11 +208,55 @@ impl Display {
         }
     }
 
+    /// Waits for a key and returns its name as in `InputProfile::bindings`.
+    pub fn wait_key_name(&self) -> Option<String> {
+        self.window.getch().and_then(key_name)
+    }
+
+    /// Waits for a key at most `ms` milliseconds, and returns its name.
+    pub fn poll_key_name(&self, ms: i32) -> Option<String> {
+        self.window.timeout(ms);
+        let key = self.wait_key_name();
+        self.window.timeout(-1);
+        key
+    }
+
     pub fn napms(&self, ms: i32) {
         pancurses::napms(ms);
     }
 }
 
+/// Returns the (y, x) positions of the cells of a piece.
+fn cells(state: &PieceState) -> Vec<(i32, i32)> {
+    let shape = core::shape(state.piece_type, state.rotation);
+    let mut res = vec![];
+    for (i, &row) in shape.iter().enumerate() {
+        for (j, cell) in row.bytes().enumerate() {
+            if cell != b'.' {
+                res.push((i as i32 + state.y as i32, j as i32 + state.x as i32));
+            }
+        }
+    }
+    res
+}
+
+fn key_name(input: pancurses::Input) -> Option<String> {
+    match input {
+        pancurses::Input::Character(' ') => Some(String::from("space")),
+        pancurses::Input::Character('\n') | pancurses::Input::KeyEnter => {
+            Some(String::from("enter"))
+        }
+        pancurses::Input::Character('\x08') | pancurses::Input::Character('\x7f')
+        | pancurses::Input::KeyBackspace => Some(String::from("backspace")),
+        pancurses::Input::Character(c) => Some(c.to_string()),
+        pancurses::Input::KeyLeft => Some(String::from("left")),
+        pancurses::Input::KeyRight => Some(String::from("right")),
+        pancurses::Input::KeyUp => Some(String::from("up")),
+        pancurses::Input::KeyDown => Some(String::from("down")),
+        _ => None,
+    }
+}
+
 impl Drop for Display {
     fn drop(&mut self) {
         pancurses::endwin();
@@ -4,7 +4,7 @@ use tetris20g_ai::agent::Agent;
 use tetris20g_ai::agent::TwoStepSearchAgent;
 use tetris20g_ai::enumeration;
 use tetris20g_ai::utility;
-use tetris20g_ai::regressor::LinearRegressor;
+use tetris20g_ai::regressor::{load_model_direct, LinearRegressor, ValueModel};
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
@@ -15,6 +15,7 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 #[wasm_bindgen]
 pub struct GameManager {
     agent: TwoStepSearchAgent,
+    model: Box<dyn ValueModel>,
     field: core::Field,
     seq: Vec<u8>,
     score_info: core::ScoreInfo,
@@ -28,6 +29,7 @@ pub struct GameManager {
 impl GameManager {
     pub fn new(param_string: &str, seq_string: &str) -> GameManager {
         let agent = TwoStepSearchAgent::new_direct(&param_string);
+        let model = load_model_direct(param_string);
         let field = core::EMPTY_FIELD;
         let seq: Vec<u8> = seq_string.bytes().collect();
         let score_info = core::ScoreInfo::new();
@@ -38,6 +40,7 @@ impl GameManager {
 
         GameManager {
             agent,
+            model,
             field,
             seq,
             score_info,
@@ -102,6 +105,15 @@ impl GameManager {
         disp
     }
 
+    pub fn render_heatmap(&self) -> Vec<f32> {
+        // returns: contributions of cells to the value of the current field, or empty if the
+        // model cannot explain values
+        match self.model.explain(&self.field) {
+            Some(explanation) => explanation.by_cell.concat(),
+            None => vec![],
+        }
+    }
+
     pub fn del_counts(&self) -> Vec<usize> {
         self.score_info.del_counts.to_vec()
     }
@@ -13,6 +13,7 @@ canvas.height = pp * 26;
 canvas.width = pp * 12;
 
 const score_info = document.getElementById("score_info")
+const heatmap_checkbox = document.getElementById("heatmap")
 
 const empty = '.'.charCodeAt();
 let colormap1 = [];
@@ -71,6 +72,17 @@ function render(m) {
     }
   }
 
+  // draw heatmap of contributions to the value, red for negative and green for positive
+  if (heatmap_checkbox.checked) {
+    const heatmap = m.render_heatmap();
+    const max = heatmap.reduce((max, v) => Math.max(max, Math.abs(v)), 0);
+    for (let i = 0; i < heatmap.length && max > 0; i++) {
+      const alpha = 0.7 * Math.abs(heatmap[i]) / max;
+      ctx.fillStyle = heatmap[i] < 0 ? `rgba(255,0,0,${alpha})` : `rgba(0,255,0,${alpha})`;
+      ctx.fillRect(offx + pp * (i % 10), offy + pp * Math.floor(i / 10), pp, pp);
+    }
+  }
+
   // draw grid
   ctx.beginPath();
   for (let j = 0; j <= 10; j++) {
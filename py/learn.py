@@ -1,4 +1,7 @@
 import argparse
+import json
+import os
+import struct
 from bitarray import bitarray
 import chainer
 import chainer.functions as F
@@ -9,6 +12,24 @@ from chainer.training import extensions
 import numpy as np
 
 
+DATASET_MAGIC = b'T20GDSET'
+DATASET_HEADER_SIZE = 32
+DATASET_FLAG_LISTWISE = 2
+
+
+def read_dataset_header(filename):
+    """Returns (dim, records, listwise) of a dataset file written by `dataset::DatasetWriter`."""
+    with open(filename, 'rb') as f:
+        header = f.read(DATASET_HEADER_SIZE)
+    if header[:8] != DATASET_MAGIC:
+        raise ValueError('{} is not a dataset file'.format(filename))
+    version, _extractor, dim, flags, records = \
+        struct.unpack('<IIIIQ', header[8:DATASET_HEADER_SIZE])
+    if version != 1:
+        raise ValueError('unsupported dataset version {}'.format(version))
+    return dim, records, bool(flags & DATASET_FLAG_LISTWISE)
+
+
 class PreprocessedDataset(chainer.dataset.DatasetMixin):
 
     def __init__(self, filenames, dim):
@@ -17,10 +38,16 @@ class PreprocessedDataset(chainer.dataset.DatasetMixin):
 
         self.data = bitarray()
         for filename in filenames:
+            file_dim, pairs, listwise = read_dataset_header(filename)
+            assert file_dim == self.dim
+            if listwise:
+                raise ValueError('{} is a listwise dataset'.format(filename))
             f = open(filename, 'rb')
+            f.seek(DATASET_HEADER_SIZE)
             s = bitarray(endian='little')
-            s.fromfile(f)
-            assert len(s) % (2 * self.per) == 0
+            # metadata section after pairs is not needed for training
+            s.frombytes(f.read(pairs * 2 * int(self.per) // 8))
+            assert len(s) == pairs * 2 * self.per
             self.data.extend(s)
 
     def __len__(self):
@@ -34,6 +61,52 @@ class PreprocessedDataset(chainer.dataset.DatasetMixin):
                         dtype=np.float32)
 
 
+class ListwiseDataset(chainer.dataset.DatasetMixin):
+    """Lists of candidates with the index of the chosen one."""
+
+    def __init__(self, filenames, dim):
+        self.dim = dim
+        self.bytes = (self.dim + 7) // 8
+
+        self.lists = []
+        for filename in filenames:
+            file_dim, records, listwise = read_dataset_header(filename)
+            assert file_dim == self.dim
+            if not listwise:
+                raise ValueError('{} is a pairwise dataset'.format(filename))
+            with open(filename, 'rb') as f:
+                f.seek(DATASET_HEADER_SIZE)
+                # a record is the number of candidates, the chosen index and the vectors
+                for _ in range(records):
+                    n, choice = struct.unpack('<II', f.read(8))
+                    data = f.read(n * self.bytes)
+                    assert len(data) == n * self.bytes
+                    self.lists.append((data, n, choice))
+
+    def __len__(self):
+        return len(self.lists)
+
+    def get_example(self, i):
+        data, n, choice = self.lists[i]
+        s = bitarray(endian='little')
+        s.frombytes(data)
+        x = np.array(s.tolist(), dtype=np.float32).reshape(n, self.bytes * 8)
+        return x[:, :self.dim], np.int32(choice)
+
+
+def concat_lists(batch, device=None):
+    """Pads the lists of a batch to the longest one, and masks padded candidates."""
+    longest = max(len(x) for x, _ in batch)
+    dim = batch[0][0].shape[1]
+    xs = np.zeros((len(batch), longest, dim), dtype=np.float32)
+    mask = np.zeros((len(batch), longest), dtype=bool)
+    for i, (x, _) in enumerate(batch):
+        xs[i, :len(x)] = x
+        mask[i, :len(x)] = True
+    choices = np.array([choice for _, choice in batch], dtype=np.int32)
+    return tuple(chainer.dataset.to_device(device, a) for a in (xs, mask, choices))
+
+
 class LinearRegression(chainer.Chain):
 
     def __init__(self, dim):
@@ -49,6 +122,28 @@ class LinearRegression(chainer.Chain):
         return ' '.join(np.char.mod('%.14f', self.l.W.data[0]))
 
 
+class MLPRegression(chainer.Chain):
+
+    def __init__(self, dim, hidden):
+        super(MLPRegression, self).__init__()
+        self.dim = dim
+        self.hidden = hidden
+        with self.init_scope():
+            self.l1 = L.Linear(dim, hidden)
+            self.l2 = L.Linear(hidden, 1)
+
+    def __call__(self, x):
+        return self.l2(F.relu(self.l1(x)))
+
+    def dump(self):
+        # format of `MlpRegressor` in regressor.rs
+        values = np.concatenate([
+            self.l1.W.data.reshape(-1), self.l1.b.data,
+            self.l2.W.data.reshape(-1), self.l2.b.data])
+        return 'mlp {} {} '.format(self.dim, self.hidden) + \
+            ' '.join(np.char.mod('%.14f', values))
+
+
 class RankLoss(chainer.Chain):
 
     def __init__(self, predictor):
@@ -71,21 +166,74 @@ class RankLoss(chainer.Chain):
         return self.loss
 
 
+class ListLoss(chainer.Chain):
+    """Softmax cross entropy of the chosen candidate among its list."""
+
+    def __init__(self, predictor):
+        super(ListLoss, self).__init__()
+
+        with self.init_scope():
+            self.predictor = predictor
+
+    def __call__(self, xs, mask, choices):
+        batch, longest, dim = xs.shape
+        y = self.predictor(F.reshape(xs, (batch * longest, dim)))
+        y = F.reshape(y, (batch, longest))
+        y = F.where(mask, y, self.xp.full(y.shape, -1e9, dtype=np.float32))
+
+        self.loss = F.softmax_cross_entropy(y, choices)
+        reporter.report({'loss': self.loss}, self)
+
+        self.accuracy = F.accuracy(y, choices)
+        reporter.report({'accuracy': self.accuracy}, self)
+
+        return self.loss
+
+
 def main():
     parser = argparse.ArgumentParser()
     parser.add_argument('--batchsize', type=int, default=128)
     parser.add_argument('--epoch', type=int, default=20)
     parser.add_argument('--gpu', type=int, default=-1)
     parser.add_argument('--loaderjob', '-j', type=int, default=1)
-    parser.add_argument('--dim', type=int, default=8184)
+    parser.add_argument('--dim', type=int, default=None,
+                        help='feature dimension. taken from dataset headers by default')
+    parser.add_argument('--hidden', type=int, default=0,
+                        help='hidden units of MLP model. 0 means linear model')
     parser.add_argument('--lr', type=float, default=1e-2)
     parser.add_argument('--weight-decay', type=float, default=0)
     parser.add_argument('--train', type=str, nargs='+', default=None)
     parser.add_argument('--val', type=str, nargs='+', default=None)
+    parser.add_argument('--manifest', type=str, default=None,
+                        help='manifest file written by dataset_generation. '
+                        'train and val shards are taken from it')
     parser.add_argument('--dump-from', type=str, default=None)
     args = parser.parse_args()
 
-    model = RankLoss(LinearRegression(args.dim))
+    if args.manifest is not None:
+        with open(args.manifest) as f:
+            manifest = json.load(f)
+        base = os.path.dirname(args.manifest)
+        args.dim = manifest['dimension']
+        args.train = [os.path.join(base, s['file'])
+                      for s in manifest['shards'] if s['split'] == 'train']
+        args.val = [os.path.join(base, s['file'])
+                    for s in manifest['shards'] if s['split'] == 'validation']
+    if args.dim is None:
+        args.dim = read_dataset_header(args.train[0])[0] \
+            if args.train else 8184
+    # listwise datasets are trained by ListLoss instead of RankLoss
+    listwise = bool(args.train) and read_dataset_header(args.train[0])[2]
+    if listwise:
+        Dataset, Loss, converter = ListwiseDataset, ListLoss, concat_lists
+    else:
+        Dataset, Loss, converter = \
+            PreprocessedDataset, RankLoss, chainer.dataset.concat_examples
+
+    if args.hidden > 0:
+        model = Loss(MLPRegression(args.dim, args.hidden))
+    else:
+        model = Loss(LinearRegression(args.dim))
     if args.gpu >= 0:
         chainer.cuda.get_device_from_id(args.gpu).use()
         model.to_gpu()
@@ -99,8 +247,8 @@ def main():
     optimizer.setup(model)
     optimizer.add_hook(chainer.optimizer.WeightDecay(args.weight_decay))
 
-    train = PreprocessedDataset(args.train, args.dim)
-    val = PreprocessedDataset(args.val, args.dim)
+    train = Dataset(args.train, args.dim)
+    val = Dataset(args.val, args.dim)
     print('dataset load: done')
 
     train_iter = chainer.iterators.MultiprocessIterator(
@@ -108,10 +256,12 @@ def main():
     val_iter = chainer.iterators.MultiprocessIterator(
         val, args.batchsize, repeat=False, n_processes=args.loaderjob)
 
-    updater = training.StandardUpdater(train_iter, optimizer, device=args.gpu)
+    updater = training.StandardUpdater(
+        train_iter, optimizer, converter=converter, device=args.gpu)
     trainer = training.Trainer(updater, (args.epoch, 'epoch'),
                                out='results')
-    trainer.extend(extensions.Evaluator(val_iter, model, device=args.gpu))
+    trainer.extend(extensions.Evaluator(
+        val_iter, model, converter=converter, device=args.gpu))
     trainer.extend(extensions.LogReport())
     trainer.extend(extensions.snapshot_object(
         model.predictor, filename='model_{.updater.epoch}'))
@@ -1,8 +1,9 @@
+use std::collections::HashMap;
 use std::f32;
 
 use core::{Field, PieceState};
 use enumeration::enumerate_multi;
-use regressor::LinearRegressor;
+use regressor::{ValueModel, load_model, load_model_direct};
 
 pub trait Agent {
     fn predict(&mut self, field: &Field, next_piece: u8, next2_piece: u8) -> Option<PieceState>;
@@ -10,43 +11,91 @@ pub trait Agent {
 }
 
 pub struct TwoStepSearchAgent {
-    regressor: LinearRegressor,
+    regressor: Box<dyn ValueModel>,
     report_string: String,
 }
 
 impl TwoStepSearchAgent {
+    /// Creates an agent from a model file. Any format accepted by `load_model` can be used.
     pub fn new(weights_file: &str) -> TwoStepSearchAgent {
-        let mut regressor = LinearRegressor::new();
-        regressor.load(&weights_file);
-        let report_string = String::from("");
-        TwoStepSearchAgent { regressor, report_string }
+        TwoStepSearchAgent::with_model(load_model(weights_file))
     }
 
     pub fn new_direct(param_string: &str) -> TwoStepSearchAgent {
-        let mut regressor = LinearRegressor::new();
-        regressor.load_direct(&param_string);
+        TwoStepSearchAgent::with_model(load_model_direct(param_string))
+    }
+
+    pub fn with_model(regressor: Box<dyn ValueModel>) -> TwoStepSearchAgent {
         let report_string = String::from("");
         TwoStepSearchAgent { regressor, report_string }
     }
 }
 
-impl Agent for TwoStepSearchAgent {
-    fn predict(&mut self, field: &Field, next_piece: u8, next2_piece: u8) -> Option<PieceState> {
-        let candidates = enumerate_multi(&field, &vec![next_piece, next2_piece]);
-        // find maximum value candidate
-        let mut best_value: f32 = f32::MIN;
-        let mut best = vec![];
-        for candidate in candidates {
-            let value = self.regressor.predict(&candidate[1].new_field);
-            if value > best_value {
-                best_value = value;
-                best = candidate.clone();
-            }
+/// Finds the placement of `next_piece` which leads to the maximum value field after placing
+/// `next2_piece`. Returns the placement and the value.
+pub fn two_step_search<M: ValueModel + ?Sized>(
+    model: &M,
+    field: &Field,
+    next_piece: u8,
+    next2_piece: u8,
+) -> Option<(PieceState, f32)> {
+    let candidates = enumerate_multi(field, &vec![next_piece, next2_piece]);
+    // find maximum value candidate
+    let mut best_value: f32 = f32::MIN;
+    let mut best = vec![];
+    for candidate in candidates {
+        let value = model.predict(&candidate[1].new_field);
+        if value > best_value {
+            best_value = value;
+            best = candidate.clone();
         }
-        if best.is_empty() {
-            return None;
+    }
+    if best.is_empty() {
+        return None;
+    }
+    Some((best[0].last_state.clone(), best_value))
+}
+
+/// Placements of a piece with their two-step search values, which are shown to humans as hints.
+pub struct Hint {
+    /// Placements sorted in descending order of the values.
+    pub placements: Vec<(PieceState, f32)>,
+}
+
+impl Hint {
+    /// Evaluates each placement of `next_piece` by the maximum value over the placements of
+    /// `next2_piece` after it.
+    pub fn new<M: ValueModel + ?Sized>(model: &M, field: &Field, next_piece: u8, next2_piece: u8)
+        -> Hint
+    {
+        let mut values: HashMap<PieceState, f32> = HashMap::new();
+        for candidate in enumerate_multi(field, &vec![next_piece, next2_piece]) {
+            let value = model.predict(&candidate[1].new_field);
+            let entry = values.entry(candidate[0].last_state.clone()).or_insert(value);
+            *entry = entry.max(value);
         }
-        let state = best[0].last_state.clone();
+        let mut placements: Vec<(PieceState, f32)> = values.into_iter().collect();
+        placements.sort_by(|x, y| y.1.total_cmp(&x.1).then_with(|| x.0.cmp(&y.0)));
+        Hint { placements }
+    }
+
+    pub fn best(&self) -> Option<&(PieceState, f32)> {
+        self.placements.first()
+    }
+
+    /// Returns the rank (starting from 1) and the value of a placement.
+    pub fn rank(&self, state: &PieceState) -> Option<(usize, f32)> {
+        self.placements
+            .iter()
+            .position(|(s, _)| s == state)
+            .map(|i| (i + 1, self.placements[i].1))
+    }
+}
+
+impl Agent for TwoStepSearchAgent {
+    fn predict(&mut self, field: &Field, next_piece: u8, next2_piece: u8) -> Option<PieceState> {
+        let (state, best_value) =
+            two_step_search(self.regressor.as_ref(), field, next_piece, next2_piece)?;
 
         self.report_string = format!("Value: {}", best_value);
 
@@ -57,3 +106,20 @@ impl Agent for TwoStepSearchAgent {
         self.report_string.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::EMPTY_FIELD;
+    use regressor::test_regressor;
+
+    #[test]
+    fn test_hint_agrees_with_search() {
+        let model = test_regressor();
+        let (state, value) = two_step_search(&model, &EMPTY_FIELD, b'T', b'L').unwrap();
+        let hint = Hint::new(&model, &EMPTY_FIELD, b'T', b'L');
+        assert_eq!(hint.best().unwrap().1, value);
+        assert_eq!(hint.rank(&state).unwrap().1, value);
+        assert!(hint.placements.windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+}
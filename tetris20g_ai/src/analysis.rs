@@ -0,0 +1,83 @@
+//! Module for analyzing a position, i.e., evaluating all placements of the current and next
+//! pieces, which the analyzer shows with their breakdown.
+use core::{FixedInfo, Field, HEIGHT, WIDTH};
+use enumeration::enumerate_multi;
+use regressor::ValueModel;
+use utility::stack_height;
+
+/// Placements of the pieces in a row and their evaluation.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub placements: Vec<FixedInfo>,
+    /// The value of the field after the last placement.
+    pub value: f32,
+    /// Lines cleared by the placements.
+    pub lines: usize,
+    /// The stack height after the last placement.
+    pub height: usize,
+    /// Empty cells under a filled cell after the last placement.
+    pub holes: usize,
+}
+
+impl Candidate {
+    /// The field after the last placement.
+    pub fn field(&self) -> &Field {
+        &self.placements.last().unwrap().new_field
+    }
+}
+
+/// Returns all candidates of placing `pieces` in order, sorted in descending order of the values.
+pub fn analyze<M: ValueModel + ?Sized>(model: &M, field: &Field, pieces: &[u8]) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = enumerate_multi(field, &pieces.to_vec())
+        .into_iter()
+        .map(|placements| {
+            let last = &placements.last().unwrap().new_field;
+            Candidate {
+                value: model.predict(last),
+                lines: placements.iter().map(|info| info.del as usize).sum(),
+                height: stack_height(last),
+                holes: count_holes(last),
+                placements,
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.value.total_cmp(&a.value));
+    candidates
+}
+
+/// Returns the number of empty cells which have a filled cell above them.
+pub fn count_holes(field: &Field) -> usize {
+    (0..WIDTH)
+        .map(|j| {
+            (0..HEIGHT)
+                .skip_while(|&i| field[i][j] == b'.')
+                .filter(|&i| field[i][j] == b'.')
+                .count()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::field_from_rows;
+    use enumeration::enumerate_single;
+    use regressor::LinearRegressor;
+
+    #[test]
+    fn test_analyze() {
+        let field = field_from_rows(&["X.........", ".X........", "XXXX..XXXX"]).unwrap();
+        assert_eq!(count_holes(&field), 1);
+        let model = LinearRegressor::new();
+        let candidates = analyze(&model, &field, b"OI");
+        let count: usize = enumerate_single(&field, b'O')
+            .iter()
+            .map(|info| enumerate_single(&info.new_field, b'I').len())
+            .sum();
+        assert_eq!(candidates.len(), count);
+        assert!(candidates.iter().any(|candidate| candidate.lines == 1));
+        let best = &candidates[0];
+        assert_eq!(best.placements.len(), 2);
+        assert_eq!(best.height, stack_height(best.field()));
+    }
+}
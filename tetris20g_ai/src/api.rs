@@ -0,0 +1,193 @@
+//! Module for the JSON API of the analysis server, through which tools and notebooks query the
+//! engine.
+//!
+//! A request and its response are JSON objects on a line each. A request has a `method` and an
+//! optional `id`, which is copied to the response, and a response has either `result` or
+//! `error`. Fields are rows from the top as accepted by `core::field_from_rows`, and piece states
+//! are in the same format as in logs. For example,
+//! `{"id": 1, "method": "suggest", "field": ["XXXX.XXXXX"], "piece": "I", "next_piece": "T"}`.
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use serde_json::{self, Value};
+
+use agent::{Agent, TwoStepSearchAgent};
+use core::{field_from_rows, field_to_rows, parse_piece, PieceState};
+use enumeration::{enumerate_single, find_command_sequence};
+use regressor::ValueModel;
+
+/// A request to the engine.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Request {
+    /// Lists the placements of a piece, with the fields after them and the cleared lines.
+    Enumerate { field: Vec<String>, piece: String },
+    /// Returns the values of fields given by the model. Larger is better.
+    Evaluate { fields: Vec<Vec<String>> },
+    /// Returns the placement chosen by the agent and its commands, or null if there is none.
+    Suggest { field: Vec<String>, piece: String, next_piece: String },
+    /// Returns the commands which move a piece from its spawn to a placement.
+    Inputs { field: Vec<String>, state: PieceState },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct RequestLine {
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    request: Request,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Response {
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// A model and an agent answering requests. The agent searches with the same model.
+pub struct Engine {
+    model: Arc<dyn ValueModel>,
+    agent: Box<dyn Agent>,
+}
+
+impl Engine {
+    pub fn new(model: Arc<dyn ValueModel>) -> Engine {
+        let agent = TwoStepSearchAgent::with_model(Box::new(model.clone()));
+        Engine { model, agent: Box::new(agent) }
+    }
+
+    /// Handles a request line and returns the response line.
+    pub fn handle_line(&mut self, line: &str) -> String {
+        let response = match serde_json::from_str::<RequestLine>(line) {
+            Ok(RequestLine { id, request }) => match self.handle(request) {
+                Ok(result) => Response { id, result: Some(result), error: None },
+                Err(error) => Response { id, result: None, error: Some(error) },
+            },
+            Err(e) => Response { id: Value::Null, result: None, error: Some(e.to_string()) },
+        };
+        serde_json::to_string(&response).unwrap()
+    }
+
+    pub fn handle(&mut self, request: Request) -> Result<Value, String> {
+        let result = match request {
+            Request::Enumerate { field, piece } => {
+                let field = field_from_rows(&field)?;
+                let placements: Vec<Value> = enumerate_single(&field, parse_piece(&piece)?)
+                    .into_iter()
+                    .map(|info| json!({
+                        "state": info.last_state,
+                        "field": field_to_rows(&info.new_field),
+                        "lines": info.del,
+                    }))
+                    .collect();
+                json!(placements)
+            }
+            Request::Evaluate { fields } => {
+                let values = fields
+                    .iter()
+                    .map(|field| Ok(self.model.predict(&field_from_rows(field)?)))
+                    .collect::<Result<Vec<f32>, String>>()?;
+                json!(values)
+            }
+            Request::Suggest { field, piece, next_piece } => {
+                let field = field_from_rows(&field)?;
+                let piece = parse_piece(&piece)?;
+                match self.agent.predict(&field, piece, parse_piece(&next_piece)?) {
+                    Some(state) => json!({
+                        "inputs": find_command_sequence(&field, piece, &state),
+                        "state": state,
+                    }),
+                    None => Value::Null,
+                }
+            }
+            Request::Inputs { field, state } => {
+                let field = field_from_rows(&field)?;
+                parse_piece(&(state.piece_type as char).to_string())?;
+                let reachable = enumerate_single(&field, state.piece_type)
+                    .iter()
+                    .any(|info| info.last_state == state);
+                if !reachable {
+                    return Err(String::from("the state cannot be reached"));
+                }
+                json!(find_command_sequence(&field, state.piece_type, &state))
+            }
+        };
+        Ok(result)
+    }
+}
+
+/// Serves requests on a listener, with a thread and an engine for each connection. The engines
+/// share the model.
+pub fn serve(listener: TcpListener, model: Arc<dyn ValueModel>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let model = model.clone();
+        thread::spawn(move || {
+            let mut engine = Engine::new(model);
+            let _ = handle_connection(&mut engine, stream);
+        });
+    }
+}
+
+fn handle_connection(engine: &mut Engine, stream: TcpStream) -> ::std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        writer.write_all(format!("{}\n", engine.handle_line(&line)).as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::EMPTY_FIELD;
+    use regressor::LinearRegressor;
+
+    #[test]
+    fn test_localhost() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, Arc::new(LinearRegressor::new())));
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = |line: &str| -> Value {
+            writer.write_all(format!("{}\n", line).as_bytes()).unwrap();
+            let mut response = String::new();
+            reader.read_line(&mut response).unwrap();
+            serde_json::from_str(&response).unwrap()
+        };
+
+        let response = request(r#"{"id": 1, "method": "enumerate", "field": [], "piece": "O"}"#);
+        assert_eq!(response["id"], 1);
+        let placements = enumerate_single(&EMPTY_FIELD, b'O').len();
+        assert_eq!(response["result"].as_array().unwrap().len(), placements);
+        let response = request(r#"{"method": "evaluate", "fields": [[], ["XXXXXXXXX."]]}"#);
+        assert_eq!(response["result"], json!([0.0, 0.0]));
+        let response =
+            request(r#"{"method": "suggest", "field": [], "piece": "T", "next_piece": "I"}"#);
+        let state = response["result"]["state"].clone();
+        let inputs = request(&format!(r#"{{"method": "inputs", "field": [], "state": {}}}"#, state));
+        assert_eq!(inputs["result"], response["result"]["inputs"]);
+        let mut unknown = state.clone();
+        unknown["piece_type"] = json!(0);
+        let response =
+            request(&format!(r#"{{"method": "inputs", "field": [], "state": {}}}"#, unknown));
+        assert!(response["error"].is_string());
+        let response = request(r#"{"id": "x", "method": "evaluate", "fields": [["bad"]]}"#);
+        assert_eq!(response["id"], "x");
+        assert!(response["error"].is_string());
+    }
+}
@@ -0,0 +1,379 @@
+//! Module for benchmarking agents on seeded episodes and summarizing the results.
+//!
+//! Episodes are run by `episode::run_seeded_episode`, so a result is reproducible from its seed.
+//! A report holds a record of every episode and a summary of each metric, and is written in JSON,
+//! or in CSV with a row per episode. Reports of agents on the same seeds can be compared episode
+//! by episode, which is much more sensitive than comparing their averages.
+//!
+//! Bootstrap resampling is seeded by `BOOTSTRAP_SEED`, so a report is determined by the results.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use serde_json;
+
+use episode::EpisodeResult;
+use statistics::{self, GeometricFit, SurvivalPoint};
+use utility;
+
+/// Names of the metrics summarized in a report.
+pub const METRICS: [&str; 5] = ["steps", "lines", "tetris_rate", "max_height", "time_per_move"];
+
+/// Returns true if a larger value of a metric in `METRICS` is better, e.g., false for
+/// `max_height`.
+pub fn higher_is_better(name: &str) -> bool {
+    match name {
+        "steps" | "lines" | "tetris_rate" => true,
+        "max_height" | "time_per_move" => false,
+        _ => panic!("unknown metric {}", name),
+    }
+}
+
+/// Percentiles shown in a summary.
+pub const PERCENTILES: [f64; 5] = [10.0, 25.0, 50.0, 75.0, 90.0];
+
+/// The number of bootstrap resamples for confidence intervals of medians.
+pub const BOOTSTRAP_RESAMPLES: usize = 1000;
+pub const BOOTSTRAP_SEED: u32 = 0;
+
+/// Parses a list of seeds such as `1-100,200,300-309`. Ranges include both ends. Seeds are
+/// checked by `utility::parse_seed`, so 0 is rejected.
+pub fn parse_seeds(s: &str) -> Result<Vec<u32>, String> {
+    let mut seeds = vec![];
+    for part in s.split(',') {
+        let parse = |x: &str| utility::parse_seed(x).map_err(|e| format!("{}: {}", part, e));
+        match part.find('-') {
+            Some(i) => {
+                let (first, last) = (parse(&part[..i])?, parse(&part[i + 1..])?);
+                if first > last {
+                    return Err(format!("{}: empty range", part));
+                }
+                seeds.extend(first..=last);
+            }
+            None => seeds.push(parse(part)?),
+        }
+    }
+    Ok(seeds)
+}
+
+/// Result of an episode in a report.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EpisodeRecord {
+    pub seed: u32,
+    pub steps: usize,
+    pub lines: usize,
+    /// The numbers of single, double, triple and tetris line clears.
+    pub del_counts: [usize; 4],
+    /// The ratio of lines cleared by tetrises.
+    pub tetris_rate: f64,
+    pub max_height: usize,
+    /// Milliseconds the agent took to decide a move, on average.
+    pub time_per_move: f64,
+    /// False if the episode was truncated by the step limit.
+    pub ended: bool,
+}
+
+impl From<&EpisodeResult> for EpisodeRecord {
+    fn from(result: &EpisodeResult) -> EpisodeRecord {
+        let score_info = &result.score_info;
+        let tetris_rate = if score_info.total_lines == 0 {
+            0.0
+        } else {
+            (score_info.del_counts[3] * 4) as f64 / score_info.total_lines as f64
+        };
+        EpisodeRecord {
+            seed: result.seed,
+            steps: score_info.steps,
+            lines: score_info.total_lines,
+            del_counts: score_info.del_counts,
+            tetris_rate,
+            max_height: result.max_height,
+            time_per_move: result.time_per_move * 1000.0,
+            ended: result.ended,
+        }
+    }
+}
+
+impl EpisodeRecord {
+    /// Returns the value of a metric in `METRICS`.
+    pub fn metric(&self, name: &str) -> f64 {
+        match name {
+            "steps" => self.steps as f64,
+            "lines" => self.lines as f64,
+            "tetris_rate" => self.tetris_rate,
+            "max_height" => self.max_height as f64,
+            "time_per_move" => self.time_per_move,
+            _ => panic!("unknown metric {}", name),
+        }
+    }
+}
+
+/// Summary of a metric over episodes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub mean: f64,
+    pub stdev: f64,
+    /// 95% confidence interval of the mean by the normal approximation.
+    pub ci95: (f64, f64),
+    pub median: f64,
+    /// 95% bootstrap confidence interval of the median.
+    pub median_ci95: (f64, f64),
+    pub min: f64,
+    /// Values at `PERCENTILES`.
+    pub percentiles: Vec<f64>,
+    pub max: f64,
+}
+
+impl Summary {
+    pub fn new(values: &[f64]) -> Summary {
+        let (mean, stdev) = utility::statistics(&values.to_vec());
+        let error = 1.96 * stdev / (values.len() as f64).sqrt();
+        Summary {
+            mean,
+            stdev,
+            ci95: (mean - error, mean + error),
+            median: statistics::median(values),
+            median_ci95: statistics::bootstrap_ci(
+                values,
+                statistics::median,
+                0.95,
+                BOOTSTRAP_RESAMPLES,
+                BOOTSTRAP_SEED,
+            ),
+            min: statistics::quantile(values, 0.0),
+            percentiles: PERCENTILES
+                .iter()
+                .map(|&p| statistics::quantile(values, p / 100.0))
+                .collect(),
+            max: statistics::quantile(values, 1.0),
+        }
+    }
+}
+
+/// Results of a benchmark.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Report {
+    /// Description of the agent, e.g., its weights file.
+    pub agent: String,
+    /// The number of lines initially filled.
+    pub lines: usize,
+    pub max_steps: Option<usize>,
+    pub episodes: Vec<EpisodeRecord>,
+    /// The number of episodes truncated by the step limit.
+    pub truncated: usize,
+    /// Map from metric names to their summaries. Note that truncated episodes make the
+    /// summary of steps biased low; `survival` and `geometric` take them into account.
+    pub summary: BTreeMap<String, Summary>,
+    /// Kaplan-Meier estimate of the probability that an episode lasts beyond a step.
+    pub survival: Vec<SurvivalPoint>,
+    /// The step where the survival falls to a half, if it does.
+    pub median_survival: Option<usize>,
+    /// Geometric fit of the episode lengths, if some episode ended.
+    pub geometric: Option<GeometricFit>,
+}
+
+impl Report {
+    pub fn new(agent: &str, lines: usize, max_steps: Option<usize>, results: &[EpisodeResult])
+        -> Report
+    {
+        let episodes: Vec<EpisodeRecord> = results.iter().map(EpisodeRecord::from).collect();
+        let summary = METRICS
+            .iter()
+            .map(|&name| {
+                let values: Vec<f64> = episodes.iter().map(|e| e.metric(name)).collect();
+                (String::from(name), Summary::new(&values))
+            })
+            .collect();
+        let lengths: Vec<(usize, bool)> = episodes.iter().map(|e| (e.steps, e.ended)).collect();
+        let survival = statistics::kaplan_meier(&lengths);
+        Report {
+            agent: String::from(agent),
+            lines,
+            max_steps,
+            truncated: episodes.iter().filter(|e| !e.ended).count(),
+            episodes,
+            summary,
+            median_survival: statistics::median_survival(&survival),
+            survival,
+            geometric: statistics::fit_geometric(&lengths),
+        }
+    }
+
+    pub fn write_json(&self, filename: &str) {
+        let mut file = File::create(filename).unwrap();
+        serde_json::to_writer_pretty(&mut file, self).unwrap();
+        file.write_all(b"\n").unwrap();
+    }
+
+    /// Writes the records of the episodes in CSV.
+    pub fn write_csv(&self, filename: &str) {
+        let mut writer = BufWriter::new(File::create(filename).unwrap());
+        writeln!(writer, "seed,steps,lines,single,double,triple,tetris,tetris_rate,max_height,\
+time_per_move,ended").unwrap();
+        for e in self.episodes.iter() {
+            let d = e.del_counts;
+            writeln!(writer, "{},{},{},{},{},{},{},{},{},{},{}", e.seed, e.steps, e.lines,
+                     d[0], d[1], d[2], d[3], e.tetris_rate, e.max_height, e.time_per_move,
+                     e.ended).unwrap();
+        }
+    }
+}
+
+/// Paired comparison of a metric between two agents run on the same seeds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub baseline: String,
+    pub candidate: String,
+    pub metric: String,
+    /// Summary of the differences, candidate minus baseline, of the episodes.
+    pub difference: Summary,
+    /// The numbers of episodes where the candidate is better, worse, and the same.
+    pub wins: usize,
+    pub losses: usize,
+    pub ties: usize,
+    /// Two-sided p-value of the Wilcoxon signed-rank test.
+    pub p_value: f64,
+}
+
+impl Comparison {
+    /// Compares reports whose episodes have the same seeds. Which value of the metric is better
+    /// is given by `higher_is_better`.
+    pub fn new(baseline: &Report, candidate: &Report, metric: &str) -> Comparison {
+        assert!(
+            baseline.episodes.iter().map(|e| e.seed).eq(candidate.episodes.iter().map(|e| e.seed)),
+            "{} and {} have different seeds",
+            baseline.agent,
+            candidate.agent
+        );
+        let differences: Vec<f64> = baseline
+            .episodes
+            .iter()
+            .zip(candidate.episodes.iter())
+            .map(|(b, c)| c.metric(metric) - b.metric(metric))
+            .collect();
+        let sign = if higher_is_better(metric) { 1.0 } else { -1.0 };
+        let improvements: Vec<f64> = differences.iter().map(|d| sign * d).collect();
+        Comparison {
+            baseline: baseline.agent.clone(),
+            candidate: candidate.agent.clone(),
+            metric: String::from(metric),
+            difference: Summary::new(&differences),
+            wins: improvements.iter().filter(|&&d| d > 0.0).count(),
+            losses: improvements.iter().filter(|&&d| d < 0.0).count(),
+            ties: improvements.iter().filter(|&&d| d == 0.0).count(),
+            p_value: wilcoxon_signed_rank(&improvements),
+        }
+    }
+}
+
+pub fn write_comparisons(comparisons: &[Comparison], filename: &str) {
+    let mut file = File::create(filename).unwrap();
+    serde_json::to_writer_pretty(&mut file, comparisons).unwrap();
+    file.write_all(b"\n").unwrap();
+}
+
+/// Returns the two-sided p-value of the Wilcoxon signed-rank test for paired differences, by
+/// the normal approximation with corrections for ties and continuity. Zero differences are
+/// ignored.
+pub fn wilcoxon_signed_rank(differences: &[f64]) -> f64 {
+    let mut nonzero: Vec<f64> = differences.iter().cloned().filter(|&d| d != 0.0).collect();
+    nonzero.sort_by(|a, b| a.abs().partial_cmp(&b.abs()).unwrap());
+    if nonzero.is_empty() {
+        return 1.0;
+    }
+    let n = nonzero.len() as f64;
+
+    // ranks of absolute values, averaged over ties
+    let mut w_plus = 0.0;
+    let mut tie_correction = 0.0;
+    let mut i = 0;
+    while i < nonzero.len() {
+        let j = (i..nonzero.len())
+            .find(|&j| nonzero[j].abs() != nonzero[i].abs())
+            .unwrap_or(nonzero.len());
+        let rank = (i + j + 1) as f64 / 2.0;
+        w_plus += rank * nonzero[i..j].iter().filter(|&&d| d > 0.0).count() as f64;
+        let t = (j - i) as f64;
+        tie_correction += t * t * t - t;
+        i = j;
+    }
+
+    let mean = n * (n + 1.0) / 4.0;
+    let variance = n * (n + 1.0) * (2.0 * n + 1.0) / 24.0 - tie_correction / 48.0;
+    if variance <= 0.0 {
+        return 1.0;
+    }
+    let z = ((w_plus - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    (2.0 * (1.0 - normal_cdf(z))).min(1.0)
+}
+
+/// The cumulative distribution function of the standard normal distribution.
+fn normal_cdf(x: f64) -> f64 {
+    // Abramowitz and Stegun 7.1.26, whose error is less than 1.5e-7
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs() / 2f64.sqrt());
+    let poly = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741
+        + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let erf = 1.0 - poly * (-x * x / 2.0).exp();
+    if x >= 0.0 { (1.0 + erf) / 2.0 } else { (1.0 - erf) / 2.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ScoreInfo;
+
+    #[test]
+    fn test_report() {
+        assert_eq!(parse_seeds("1-3,10").unwrap(), vec![1, 2, 3, 10]);
+        assert!(parse_seeds("3-1").is_err());
+        assert!(parse_seeds("x").is_err());
+        assert!(parse_seeds("0-99").is_err());
+
+        let results: Vec<EpisodeResult> = (0..5)
+            .map(|i| {
+                let mut score_info = ScoreInfo::new();
+                for _ in 0..i {
+                    score_info.update(4);
+                }
+                score_info.update(2);
+                EpisodeResult {
+                    seed: i as u32,
+                    score_info,
+                    ended: i < 4,
+                    max_height: 10,
+                    time_per_move: 0.001,
+                }
+            })
+            .collect();
+        let report = Report::new("test", 0, Some(5), &results);
+        assert_eq!(report.truncated, 1);
+        assert_eq!(report.episodes[1].tetris_rate, 4.0 / 6.0);
+        let steps = &report.summary["steps"];
+        assert_eq!(steps.mean, 3.0);
+        assert_eq!(steps.percentiles, vec![1.4, 2.0, 3.0, 4.0, 4.6]);
+        assert!(steps.ci95.0 < 3.0 && 3.0 < steps.ci95.1);
+        assert_eq!(report.median_survival, Some(3));
+        assert_eq!(report.geometric.as_ref().unwrap().death_rate, 4.0 / 19.0);
+
+        // the candidate survives longer with lower stacks
+        let mut better = report.clone();
+        for e in better.episodes.iter_mut() {
+            e.steps += 1;
+            e.max_height -= 1;
+        }
+        for &metric in ["steps", "max_height"].iter() {
+            let comparison = Comparison::new(&report, &better, metric);
+            assert_eq!((comparison.wins, comparison.losses), (5, 0));
+        }
+        assert_eq!(Comparison::new(&report, &better, "max_height").difference.mean, -1.0);
+    }
+
+    #[test]
+    fn test_wilcoxon_signed_rank() {
+        let increasing: Vec<f64> = (1..11).map(|x| x as f64).collect();
+        // scipy.stats.wilcoxon(range(1, 11), correction=True, method="approx")
+        assert!((wilcoxon_signed_rank(&increasing) - 0.005922).abs() < 1e-5);
+        assert!(wilcoxon_signed_rank(&[1.0, -1.0, 2.0, -2.0, 0.0]) > 0.999);
+        assert_eq!(wilcoxon_signed_rank(&[0.0, 0.0]), 1.0);
+        assert!((normal_cdf(1.96) - 0.975).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,273 @@
+//! Module for driving an agent by a line-delimited JSON protocol, modeled on the Tetris Bot
+//! Protocol (TBP), so that other games and test harnesses can use the AI as an engine.
+//!
+//! Each line is a message with a `type`. The bot first sends `info`. The frontend sends `rules`,
+//! to which the bot replies `ready` or `error`, and then `start` with the board and the queue.
+//! After that, `suggest` is answered by a `suggestion`, and `play` and `new_piece` update the
+//! game. `stop` ends the game and `quit` ends the session.
+//!
+//! Unlike TBP, the board has `HEIGHT` rows from the bottom, hold is not supported, and a location
+//! is a `PieceState` of this crate, i.e., the top-left corner of the 4x4 box of the piece, with
+//! rows counted from the top, and the ARS rotation. A suggested move also has the commands which
+//! move the piece from its spawn to the location.
+use std::collections::VecDeque;
+use serde_json;
+
+use agent::Agent;
+use core::{fix_piece, parse_piece, Command, Field, PieceState, Rules};
+use core::{EMPTY_FIELD, HEIGHT, WIDTH};
+use enumeration::{enumerate_single, find_command_sequence};
+
+/// A message from the frontend to the bot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrontendMessage {
+    /// The rules of the game. Only the default rules are supported.
+    Rules {
+        #[serde(default)]
+        rules: Option<Rules>,
+    },
+    Start {
+        /// Rows from the bottom. A cell is null if it is empty, or the name of a piece or `G`
+        /// for garbage.
+        board: Vec<Vec<Option<String>>>,
+        /// The current piece and the previews.
+        queue: Vec<String>,
+        #[serde(default)]
+        hold: Option<String>,
+    },
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: Move,
+    },
+    NewPiece {
+        piece: String,
+    },
+    Stop,
+    Quit,
+}
+
+/// A message from the bot to the frontend.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+        features: Vec<String>,
+    },
+    Ready,
+    /// Moves in the order of preference. This is empty if no piece can be placed.
+    Suggestion {
+        moves: Vec<Move>,
+    },
+    Error {
+        reason: String,
+    },
+}
+
+impl BotMessage {
+    /// Serializes the message into a line without the line break.
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Where a piece is placed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Location {
+    #[serde(rename = "type")]
+    pub piece: String,
+    pub x: i8,
+    pub y: i8,
+    pub rotation: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Move {
+    pub location: Location,
+    /// Commands from the spawn to the location. Only suggestions have them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inputs: Option<Vec<Command>>,
+}
+
+/// A game being played by a bot.
+struct BotGame {
+    field: Field,
+    queue: VecDeque<u8>,
+}
+
+/// The state of a bot session.
+pub struct BotSession {
+    agent: Box<dyn Agent>,
+    game: Option<BotGame>,
+    quit: bool,
+}
+
+impl BotSession {
+    pub fn new(agent: Box<dyn Agent>) -> BotSession {
+        BotSession { agent, game: None, quit: false }
+    }
+
+    /// The first message of the bot.
+    pub fn info() -> BotMessage {
+        BotMessage::Info {
+            name: String::from("tetris20g_ai"),
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            author: String::from(env!("CARGO_PKG_AUTHORS")),
+            features: vec![],
+        }
+    }
+
+    /// True after `quit` is received.
+    pub fn is_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Handles a line from the frontend, and returns the line of the reply if any.
+    pub fn handle_line(&mut self, line: &str) -> Option<String> {
+        let reply = match serde_json::from_str(line) {
+            Ok(message) => self.handle(message),
+            Err(e) => Some(BotMessage::Error { reason: e.to_string() }),
+        };
+        reply.map(|reply| reply.to_line())
+    }
+
+    /// Handles a message, and returns the reply if any.
+    pub fn handle(&mut self, message: FrontendMessage) -> Option<BotMessage> {
+        let result = match message {
+            FrontendMessage::Rules { rules } => match rules {
+                Some(ref rules) if *rules != Rules::default() => {
+                    Err(String::from("only the default rules are supported"))
+                }
+                _ => Ok(Some(BotMessage::Ready)),
+            },
+            FrontendMessage::Start { board, queue, hold } => {
+                self.start(&board, &queue, hold.as_ref()).map(|_| None)
+            }
+            FrontendMessage::Suggest => self.suggest().map(Some),
+            FrontendMessage::Play { mv } => self.play(&mv).map(|_| None),
+            FrontendMessage::NewPiece { piece } => self.game_mut().and_then(|game| {
+                game.queue.push_back(parse_piece(&piece)?);
+                Ok(None)
+            }),
+            FrontendMessage::Stop => {
+                self.game = None;
+                Ok(None)
+            }
+            FrontendMessage::Quit => {
+                self.quit = true;
+                Ok(None)
+            }
+        };
+        result.unwrap_or_else(|reason| Some(BotMessage::Error { reason }))
+    }
+
+    fn game_mut(&mut self) -> Result<&mut BotGame, String> {
+        self.game.as_mut().ok_or_else(|| String::from("the game has not started"))
+    }
+
+    fn start(&mut self, board: &[Vec<Option<String>>], queue: &[String], hold: Option<&String>)
+        -> Result<(), String>
+    {
+        if hold.is_some() {
+            return Err(String::from("hold is not supported"));
+        }
+        let mut field = EMPTY_FIELD;
+        for (i, row) in board.iter().enumerate() {
+            if row.len() != WIDTH {
+                return Err(format!("row {} does not have {} cells", i, WIDTH));
+            }
+            for (j, cell) in row.iter().enumerate() {
+                let cell = match cell.as_ref().map(|cell| cell.as_str()) {
+                    None => continue,
+                    Some("G") => b'X',
+                    Some(piece) => parse_piece(piece)?,
+                };
+                if i >= HEIGHT {
+                    return Err(format!("the board is higher than {} rows", HEIGHT));
+                }
+                field[HEIGHT - 1 - i][j] = cell;
+            }
+        }
+        let queue = queue.iter().map(|piece| parse_piece(piece)).collect::<Result<_, _>>()?;
+        self.game = Some(BotGame { field, queue });
+        Ok(())
+    }
+
+    fn suggest(&mut self) -> Result<BotMessage, String> {
+        let game = self.game.as_ref().ok_or_else(|| String::from("the game has not started"))?;
+        if game.queue.len() < 2 {
+            return Err(String::from("the queue needs the current piece and a preview"));
+        }
+        let piece = game.queue[0];
+        let moves = match self.agent.predict(&game.field, piece, game.queue[1]) {
+            Some(state) => vec![Move {
+                location: Location {
+                    piece: (state.piece_type as char).to_string(),
+                    x: state.x,
+                    y: state.y,
+                    rotation: state.rotation,
+                },
+                inputs: Some(find_command_sequence(&game.field, piece, &state)),
+            }],
+            None => vec![],
+        };
+        Ok(BotMessage::Suggestion { moves })
+    }
+
+    fn play(&mut self, mv: &Move) -> Result<(), String> {
+        let game = self.game_mut()?;
+        let piece = parse_piece(&mv.location.piece)?;
+        if game.queue.front() != Some(&piece) {
+            return Err(format!("{} is not the current piece", mv.location.piece));
+        }
+        let location = &mv.location;
+        let state: PieceState = enumerate_single(&game.field, piece)
+            .into_iter()
+            .map(|info| info.last_state)
+            .find(|s| (s.x, s.y, s.rotation) == (location.x, location.y, location.rotation))
+            .ok_or_else(|| String::from("the location cannot be reached"))?;
+        game.field = fix_piece(&game.field, &state).0;
+        game.queue.pop_front();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agent::TwoStepSearchAgent;
+    use regressor::LinearRegressor;
+
+    #[test]
+    fn test_session() {
+        let agent = TwoStepSearchAgent::with_model(Box::new(LinearRegressor::new()));
+        let mut session = BotSession::new(Box::new(agent));
+        let ready = session.handle_line(r#"{"type": "rules"}"#);
+        assert_eq!(ready, Some(String::from(r#"{"type":"ready"}"#)));
+        assert!(session.handle_line(r#"{"type": "suggest"}"#).unwrap().contains("error"));
+
+        let mut board = vec![vec![None; WIDTH]; 3];
+        board[0][0] = Some(String::from("G"));
+        let start = FrontendMessage::Start { board, queue: vec![String::from("T")], hold: None };
+        assert_eq!(session.handle(start), None);
+        assert!(session.handle_line(r#"{"type": "suggest"}"#).unwrap().contains("error"));
+        session.handle_line(r#"{"type": "new_piece", "piece": "I"}"#);
+
+        let mv = match session.handle(FrontendMessage::Suggest) {
+            Some(BotMessage::Suggestion { mut moves }) => moves.remove(0),
+            reply => panic!("{:?}", reply),
+        };
+        assert_eq!(mv.location.piece, "T");
+        assert_eq!(mv.inputs.as_ref().unwrap().last(), Some(&Command::Fix));
+        assert_eq!(session.handle(FrontendMessage::Play { mv: mv.clone() }), None);
+        // the T is no longer the current piece
+        assert!(session.handle(FrontendMessage::Play { mv }).is_some());
+
+        session.handle_line(r#"{"type": "quit"}"#);
+        assert!(session.is_quit());
+    }
+}
@@ -1,4 +1,11 @@
 //! Core environment for 20G tetris.
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use serde_json;
 
 /// The height of a field.
 pub const HEIGHT: usize = 20;
@@ -129,6 +136,49 @@ pub fn cycle(piece_type: u8) -> usize {
     }
 }
 
+/// Rule set of the simulated game. This module implements only one rule set, but it is recorded
+/// in logs so that they stay interpretable if other rules are supported later.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Rules {
+    /// Rotation system. `ars` is the Arika rotation system.
+    pub rotation: String,
+    /// Gravity in rows per frame. 20 means that pieces drop to the bottom instantly.
+    pub gravity: u32,
+    pub width: usize,
+    pub height: usize,
+    /// Frames a grounded piece waits before it locks. None means that the game is turn-based,
+    /// i.e., pieces are locked only by commands.
+    #[serde(default)]
+    pub lock_delay: Option<u32>,
+    /// Frames between locking a piece and the appearance of the next one, i.e., ARE.
+    #[serde(default)]
+    pub are: Option<u32>,
+}
+
+impl Rules {
+    /// Returns rules for real-time play with given timing in frames.
+    pub fn real_time(lock_delay: u32, are: u32) -> Rules {
+        Rules {
+            lock_delay: Some(lock_delay),
+            are: Some(are),
+            ..Rules::default()
+        }
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            rotation: String::from("ars"),
+            gravity: 20,
+            width: WIDTH,
+            height: HEIGHT,
+            lock_delay: None,
+            are: None,
+        }
+    }
+}
+
 /// The state of a piece we are currently manipulating.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, PartialOrd, Ord, Clone, Hash)]
 pub struct PieceState {
@@ -140,7 +190,7 @@ pub struct PieceState {
 }
 
 /// Command input for manipulation of a piece.
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Command {
     Move(i8, i8), // (dx, rotate)
     Fix,
@@ -324,7 +374,184 @@ pub fn new_piece(piece_type: u8) -> PieceState {
     }
 }
 
+/// Parses the name of a piece such as `T`.
+pub fn parse_piece(name: &str) -> Result<u8, String> {
+    match name.as_bytes() {
+        [piece] if b"IOSZJLT".contains(piece) => Ok(*piece),
+        _ => Err(format!("unknown piece {}", name)),
+    }
+}
+
+/// Parses rows of a field from the top, e.g., `["..TTT.....", "XXXXXX.XXX"]`. Rows are aligned
+/// to the bottom if there are fewer than `HEIGHT` rows.
+pub fn field_from_rows<S: AsRef<str>>(rows: &[S]) -> Result<Field, String> {
+    if rows.len() > HEIGHT {
+        return Err(format!("more than {} rows", HEIGHT));
+    }
+    let mut field = EMPTY_FIELD;
+    for (row, line) in field[HEIGHT - rows.len()..].iter_mut().zip(rows.iter()) {
+        let line = line.as_ref();
+        if line.len() != WIDTH || !line.bytes().all(|c| b".XIOSZJLT".contains(&c)) {
+            return Err(format!("invalid row `{}`", line));
+        }
+        row.copy_from_slice(line.as_bytes());
+    }
+    Ok(field)
+}
+
+/// Returns rows of a field from the highest non-empty one to the bottom, which
+/// `field_from_rows` accepts.
+pub fn field_to_rows(field: &Field) -> Vec<String> {
+    field
+        .iter()
+        .skip_while(|row| row.iter().all(|&cell| cell == b'.'))
+        .map(|row| String::from_utf8_lossy(row).into_owned())
+        .collect()
+}
+
+/// Parses piece types in a string such as `TIO`.
+fn parse_pieces(pieces: &str) -> Result<Vec<u8>, String> {
+    pieces.chars().map(|c| parse_piece(c.encode_utf8(&mut [0; 4]))).collect()
+}
+
+/// A field, a piece queue starting with the current piece, and a held piece, from which a game
+/// starts, e.g., for practicing a hard position.
+///
+/// In files, the field is a list of rows as in `field_to_rows`, and the queue is a string of piece
+/// types, e.g., `{"field": ["..TTT.....", "XXXXXX.XXX"], "queue": "TIO"}`.
+///
+/// The text notation, which `FromStr` and `Display` implement, is `rows:queue` or
+/// `rows:queue:hold`. Rows are given from the highest non-empty one to the bottom, separated by
+/// `/`, and a number stands for that many empty cells, e.g., `2TTT5/XXXXXX1XXX:TIO`. The rows of
+/// an empty field are an empty string, e.g., `:TIO`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "PositionFile", into = "PositionFile")]
+pub struct Position {
+    pub field: Field,
+    pub queue: Vec<u8>,
+    pub hold: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PositionFile {
+    field: Vec<String>,
+    queue: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hold: Option<String>,
+}
+
+impl TryFrom<PositionFile> for Position {
+    type Error = String;
+
+    fn try_from(file: PositionFile) -> Result<Position, String> {
+        Ok(Position {
+            field: field_from_rows(&file.field)?,
+            queue: parse_pieces(&file.queue)?,
+            hold: file.hold.map(|hold| parse_piece(&hold)).transpose()?,
+        })
+    }
+}
+
+impl From<Position> for PositionFile {
+    fn from(position: Position) -> PositionFile {
+        PositionFile {
+            field: field_to_rows(&position.field),
+            queue: String::from_utf8(position.queue).unwrap(),
+            hold: position.hold.map(|hold| (hold as char).to_string()),
+        }
+    }
+}
+
+impl Position {
+    pub fn load(filename: &str) -> Position {
+        Position::read(filename).unwrap_or_else(|e| panic!("{}: {}", filename, e))
+    }
+
+    /// Reads a position like `load`, but returns an error instead of panicking.
+    pub fn read(filename: &str) -> Result<Position, String> {
+        let mut file = OpenOptions::new().read(true).open(filename).map_err(|e| e.to_string())?;
+        let mut all = String::new();
+        file.read_to_string(&mut all).map_err(|e| e.to_string())?;
+        serde_json::from_str(&all).map_err(|e| e.to_string())
+    }
+
+    /// Saves the position, creating the parent directory if needed.
+    pub fn save(&self, filename: &str) {
+        if let Some(dir) = Path::new(filename).parent() {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(filename, serde_json::to_string(self).unwrap() + "\n").unwrap();
+    }
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Position, String> {
+        let parts: Vec<&str> = s.trim().split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(format!("`{}` is not of the form rows:queue[:hold]", s));
+        }
+        let mut rows = vec![];
+        if !parts[0].is_empty() {
+            for compact in parts[0].split('/') {
+                let mut row = String::new();
+                let mut empty = 0;
+                for c in compact.chars() {
+                    match c.to_digit(10) {
+                        Some(d) => empty = empty * 10 + d as usize,
+                        None => {
+                            row.extend((0..empty).map(|_| '.'));
+                            empty = 0;
+                            row.push(c);
+                        }
+                    }
+                }
+                row.extend((0..empty).map(|_| '.'));
+                rows.push(row);
+            }
+        }
+        let hold = match parts.get(2) {
+            Some(hold) if !hold.is_empty() => Some(parse_piece(hold)?),
+            _ => None,
+        };
+        Ok(Position { field: field_from_rows(&rows)?, queue: parse_pieces(parts[1])?, hold })
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows: Vec<String> = field_to_rows(&self.field)
+            .iter()
+            .map(|row| {
+                let mut compact = String::new();
+                let mut empty = 0;
+                for c in row.chars().chain(Some('$')) {
+                    if c == '.' {
+                        empty += 1;
+                        continue;
+                    }
+                    if empty > 0 {
+                        compact += &empty.to_string();
+                        empty = 0;
+                    }
+                    if c != '$' {
+                        compact.push(c);
+                    }
+                }
+                compact
+            })
+            .collect();
+        write!(f, "{}:{}", rows.join("/"), String::from_utf8_lossy(&self.queue))?;
+        if let Some(hold) = self.hold {
+            write!(f, ":{}", hold as char)?;
+        }
+        Ok(())
+    }
+}
+
 /// Score information
+#[derive(Debug, Clone)]
 pub struct ScoreInfo {
     pub del_counts: [usize; 4],
     pub total_lines: usize,
@@ -348,3 +575,30 @@ impl ScoreInfo {
         self.steps += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_notation() {
+        let notation = "2TTT5/XXXXXX1XXX/10/IIII1XXXXX:TIO:L";
+        let position: Position = notation.parse().unwrap();
+        assert_eq!(field_to_rows(&position.field),
+                   vec!["..TTT.....", "XXXXXX.XXX", "..........", "IIII.XXXXX"]);
+        assert_eq!(position.queue, b"TIO".to_vec());
+        assert_eq!(position.hold, Some(b'L'));
+        assert_eq!(position.to_string(), notation);
+        assert_eq!("..TTT.....:T".parse::<Position>().unwrap().to_string(), "2TTT5:T");
+
+        let empty: Position = ":IO".parse().unwrap();
+        assert_eq!(empty, Position { field: EMPTY_FIELD, queue: b"IO".to_vec(), hold: None });
+        assert_eq!(empty.to_string(), ":IO");
+        assert_eq!(serde_json::to_string(&empty).unwrap(), r#"{"field":[],"queue":"IO"}"#);
+
+        assert!("XXXX:T".parse::<Position>().is_err());
+        assert!(":TX".parse::<Position>().is_err());
+        assert!("10:T:I:O".parse::<Position>().is_err());
+        assert!("10".parse::<Position>().is_err());
+    }
+}
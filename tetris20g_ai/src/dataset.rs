@@ -0,0 +1,468 @@
+//! Module for the binary dataset container.
+//!
+//! A dataset file consists of a fixed size header, records of bit-packed feature vectors, and an
+//! optional metadata section. A record is a pair of vectors (the preferred one first) in a
+//! pairwise dataset, or a list of candidate vectors with the index of the chosen one in a
+//! listwise dataset. All integers are little endian.
+//!
+//! | Offset | Size | Content                                             |
+//! |--------|------|-----------------------------------------------------|
+//! | 0      | 8    | magic `T20GDSET`                                    |
+//! | 8      | 4    | format version                                      |
+//! | 12     | 4    | feature extractor id                                |
+//! | 16     | 4    | dimension of a feature vector                       |
+//! | 20     | 4    | flags (bit 0: metadata section exists, bit 1: listwise) |
+//! | 24     | 8    | the number of records                               |
+//! | 32     | -    | records                                             |
+//!
+//! A vector occupies `(dim + 7) / 8` bytes. A listwise record starts with the number of
+//! candidates and the index of the chosen candidate (4 bytes each) followed by the vectors.
+//!
+//! The metadata section has a 12 byte entry (source id, step, rank) per record, followed by the
+//! source table: the number of sources, and length-prefixed UTF-8 names. For a listwise record,
+//! rank is the index of the chosen candidate.
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+/// Magic number at the beginning of a dataset file.
+pub const MAGIC: &[u8; 8] = b"T20GDSET";
+/// The current format version.
+pub const VERSION: u32 = 1;
+/// Size of the header in bytes.
+pub const HEADER_SIZE: u64 = 32;
+
+const FLAG_METADATA: u32 = 1;
+const FLAG_LISTWISE: u32 = 2;
+const METADATA_RECORD_SIZE: u64 = 12;
+
+/// Kind of records in a dataset.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetKind {
+    /// Pairs of the preferred field and an alternative.
+    #[default]
+    Pairwise,
+    /// Lists of candidates with the index of the chosen one.
+    Listwise,
+}
+
+impl FromStr for DatasetKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DatasetKind, String> {
+        match s {
+            "pairwise" => Ok(DatasetKind::Pairwise),
+            "listwise" => Ok(DatasetKind::Listwise),
+            _ => Err(format!("unknown dataset kind: {} (expected `pairwise` or `listwise`)", s)),
+        }
+    }
+}
+
+/// Header of a dataset file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatasetHeader {
+    pub version: u32,
+    pub kind: DatasetKind,
+    pub feature_extractor: u32,
+    pub dimension: u32,
+    pub has_metadata: bool,
+    pub records: u64,
+}
+
+impl DatasetHeader {
+    /// Bytes of a single bit-packed feature vector.
+    pub fn vector_bytes(&self) -> u64 {
+        (self.dimension as u64).div_ceil(8)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = MAGIC.to_vec();
+        res.extend_from_slice(&self.version.to_le_bytes());
+        res.extend_from_slice(&self.feature_extractor.to_le_bytes());
+        res.extend_from_slice(&self.dimension.to_le_bytes());
+        let mut flags = if self.has_metadata { FLAG_METADATA } else { 0 };
+        if self.kind == DatasetKind::Listwise {
+            flags |= FLAG_LISTWISE;
+        }
+        res.extend_from_slice(&flags.to_le_bytes());
+        res.extend_from_slice(&self.records.to_le_bytes());
+        res
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_SIZE as usize]) -> io::Result<DatasetHeader> {
+        if &bytes[..8] != MAGIC {
+            return Err(invalid_data("not a dataset file (magic number mismatch)"));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let mut records = [0u8; 8];
+        records.copy_from_slice(&bytes[24..32]);
+        let header = DatasetHeader {
+            version: u32_at(8),
+            kind: if u32_at(20) & FLAG_LISTWISE != 0 {
+                DatasetKind::Listwise
+            } else {
+                DatasetKind::Pairwise
+            },
+            feature_extractor: u32_at(12),
+            dimension: u32_at(16),
+            has_metadata: u32_at(20) & FLAG_METADATA != 0,
+            records: u64::from_le_bytes(records),
+        };
+        if header.version != VERSION {
+            return Err(invalid_data(&format!("unsupported version {}", header.version)));
+        }
+        Ok(header)
+    }
+}
+
+/// Optional information attached to a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairMetadata {
+    /// Name of the log file the pair comes from.
+    pub source: String,
+    /// Step in the log file.
+    pub step: i32,
+    /// Position of the alternative among the candidates of the step, or the index of the
+    /// chosen candidate for a listwise record.
+    pub rank: u32,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Writes a dataset file. `finish` must be called after all records are written, because the
+/// number of records in the header and the metadata section are written there.
+pub struct DatasetWriter {
+    file: BufWriter<File>,
+    header: DatasetHeader,
+    records: Vec<(u32, i32, u32)>,
+    sources: Vec<String>,
+}
+
+impl DatasetWriter {
+    pub fn create(
+        filename: &str,
+        kind: DatasetKind,
+        feature_extractor: u32,
+        dimension: usize,
+        with_metadata: bool,
+    ) -> io::Result<DatasetWriter> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(filename)?;
+        let header = DatasetHeader {
+            version: VERSION,
+            kind,
+            feature_extractor,
+            dimension: dimension as u32,
+            has_metadata: with_metadata,
+            records: 0,
+        };
+        let mut file = BufWriter::new(file);
+        file.write_all(&header.to_bytes())?;
+        Ok(DatasetWriter {
+            file,
+            header,
+            records: vec![],
+            sources: vec![],
+        })
+    }
+
+    /// Returns the number of records written so far.
+    pub fn records(&self) -> u64 {
+        self.header.records
+    }
+
+    /// Writes a pair of bit-packed feature vectors, the preferred one first.
+    pub fn write_pair(&mut self, feature0: &[u8], feature1: &[u8], metadata: Option<&PairMetadata>)
+        -> io::Result<()>
+    {
+        assert_eq!(self.header.kind, DatasetKind::Pairwise);
+        let bytes = self.header.vector_bytes() as usize;
+        assert!(feature0.len() == bytes && feature1.len() == bytes);
+        self.file.write_all(feature0)?;
+        self.file.write_all(feature1)?;
+        self.push_metadata(metadata);
+        Ok(())
+    }
+
+    /// Writes a list of bit-packed feature vectors with the index of the chosen one.
+    pub fn write_list(&mut self, features: &[Vec<u8>], choice: u32, metadata: Option<&PairMetadata>)
+        -> io::Result<()>
+    {
+        assert_eq!(self.header.kind, DatasetKind::Listwise);
+        assert!((choice as usize) < features.len());
+        let bytes = self.header.vector_bytes() as usize;
+        self.file.write_all(&(features.len() as u32).to_le_bytes())?;
+        self.file.write_all(&choice.to_le_bytes())?;
+        for feature in features {
+            assert_eq!(feature.len(), bytes);
+            self.file.write_all(feature)?;
+        }
+        self.push_metadata(metadata);
+        Ok(())
+    }
+
+    fn push_metadata(&mut self, metadata: Option<&PairMetadata>) {
+        if self.header.has_metadata {
+            let metadata = metadata.expect("metadata is required for this dataset");
+            let source = match self.sources.iter().position(|s| *s == metadata.source) {
+                Some(idx) => idx,
+                None => {
+                    self.sources.push(metadata.source.clone());
+                    self.sources.len() - 1
+                }
+            };
+            self.records.push((source as u32, metadata.step, metadata.rank));
+        }
+        self.header.records += 1;
+    }
+
+    /// Writes the metadata section and the final header.
+    pub fn finish(mut self) -> io::Result<DatasetHeader> {
+        if self.header.has_metadata {
+            for &(source, step, rank) in self.records.iter() {
+                self.file.write_all(&source.to_le_bytes())?;
+                self.file.write_all(&step.to_le_bytes())?;
+                self.file.write_all(&rank.to_le_bytes())?;
+            }
+            self.file.write_all(&(self.sources.len() as u32).to_le_bytes())?;
+            for source in self.sources.iter() {
+                self.file.write_all(&(source.len() as u32).to_le_bytes())?;
+                self.file.write_all(source.as_bytes())?;
+            }
+        }
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&self.header.to_bytes())?;
+        self.file.flush()?;
+        Ok(self.header)
+    }
+}
+
+/// Reads a dataset file with random access to records.
+pub struct DatasetReader {
+    file: BufReader<File>,
+    header: DatasetHeader,
+    /// Offsets of records. Only used for a listwise dataset.
+    offsets: Vec<u64>,
+    records: Vec<(u32, i32, u32)>,
+    sources: Vec<String>,
+}
+
+impl DatasetReader {
+    /// Opens a dataset file and validates its header and size.
+    pub fn open(filename: &str) -> io::Result<DatasetReader> {
+        let mut file = BufReader::new(File::open(filename)?);
+        let mut bytes = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut bytes)?;
+        let header = DatasetHeader::from_bytes(&bytes)?;
+
+        let len = file.get_ref().metadata()?.len();
+        let mut offsets = vec![];
+        let body = match header.kind {
+            DatasetKind::Pairwise => header.records * 2 * header.vector_bytes(),
+            DatasetKind::Listwise => {
+                // scan the lengths of lists to build the offset table
+                let mut offset = HEADER_SIZE;
+                let mut word = [0u8; 4];
+                for _ in 0..header.records {
+                    offsets.push(offset);
+                    file.seek(SeekFrom::Start(offset))?;
+                    file.read_exact(&mut word)?;
+                    let count = u32::from_le_bytes(word) as u64;
+                    file.read_exact(&mut word)?;
+                    if u32::from_le_bytes(word) as u64 >= count {
+                        return Err(invalid_data("chosen index is out of the list"));
+                    }
+                    offset += 8 + count * header.vector_bytes();
+                }
+                offset - HEADER_SIZE
+            }
+        };
+        if len < HEADER_SIZE + body {
+            return Err(invalid_data("file is shorter than the number of records in the header"));
+        }
+
+        let mut records = vec![];
+        let mut sources = vec![];
+        if header.has_metadata {
+            file.seek(SeekFrom::Start(HEADER_SIZE + body))?;
+            let mut record = [0u8; METADATA_RECORD_SIZE as usize];
+            for _ in 0..header.records {
+                file.read_exact(&mut record)?;
+                let at = |i: usize| [record[i], record[i + 1], record[i + 2], record[i + 3]];
+                records.push((
+                    u32::from_le_bytes(at(0)),
+                    i32::from_le_bytes(at(4)),
+                    u32::from_le_bytes(at(8)),
+                ));
+            }
+            let mut word = [0u8; 4];
+            file.read_exact(&mut word)?;
+            for _ in 0..u32::from_le_bytes(word) {
+                file.read_exact(&mut word)?;
+                let mut name = vec![0u8; u32::from_le_bytes(word) as usize];
+                file.read_exact(&mut name)?;
+                sources.push(String::from_utf8(name).map_err(|_| invalid_data("invalid source"))?);
+            }
+            if records.iter().any(|&(source, _, _)| source as usize >= sources.len()) {
+                return Err(invalid_data("metadata refers to an unknown source"));
+            }
+        } else if len != HEADER_SIZE + body {
+            return Err(invalid_data("unexpected trailing bytes"));
+        }
+
+        Ok(DatasetReader {
+            file,
+            header,
+            offsets,
+            records,
+            sources,
+        })
+    }
+
+    pub fn header(&self) -> &DatasetHeader {
+        &self.header
+    }
+
+    /// Returns the `idx`-th pair as bit-packed feature vectors.
+    pub fn raw_pair(&mut self, idx: u64) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        assert_eq!(self.header.kind, DatasetKind::Pairwise);
+        assert!(idx < self.header.records);
+        let bytes = self.header.vector_bytes();
+        self.file.seek(SeekFrom::Start(HEADER_SIZE + idx * 2 * bytes))?;
+        let mut feature0 = vec![0u8; bytes as usize];
+        let mut feature1 = vec![0u8; bytes as usize];
+        self.file.read_exact(&mut feature0)?;
+        self.file.read_exact(&mut feature1)?;
+        Ok((feature0, feature1))
+    }
+
+    /// Returns the `idx`-th pair as feature vectors.
+    pub fn pair(&mut self, idx: u64) -> io::Result<(Vec<bool>, Vec<bool>)> {
+        let dim = self.header.dimension as usize;
+        let (feature0, feature1) = self.raw_pair(idx)?;
+        Ok((vecu8_to_vecbool(&feature0, dim), vecu8_to_vecbool(&feature1, dim)))
+    }
+
+    /// Returns the `idx`-th list as feature vectors and the index of the chosen one.
+    pub fn list(&mut self, idx: u64) -> io::Result<(Vec<Vec<bool>>, u32)> {
+        assert_eq!(self.header.kind, DatasetKind::Listwise);
+        let dim = self.header.dimension as usize;
+        self.file.seek(SeekFrom::Start(self.offsets[idx as usize]))?;
+        let mut word = [0u8; 4];
+        self.file.read_exact(&mut word)?;
+        let count = u32::from_le_bytes(word);
+        self.file.read_exact(&mut word)?;
+        let choice = u32::from_le_bytes(word);
+        let mut features = vec![];
+        let mut buf = vec![0u8; self.header.vector_bytes() as usize];
+        for _ in 0..count {
+            self.file.read_exact(&mut buf)?;
+            features.push(vecu8_to_vecbool(&buf, dim));
+        }
+        Ok((features, choice))
+    }
+
+    /// Returns the metadata of the `idx`-th record if the dataset has it.
+    pub fn metadata(&self, idx: u64) -> Option<PairMetadata> {
+        self.records.get(idx as usize).map(|&(source, step, rank)| PairMetadata {
+            source: self.sources[source as usize].clone(),
+            step,
+            rank,
+        })
+    }
+
+    /// Returns the names of all sources in the metadata.
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+}
+
+/// Packs a feature vector into bytes. The `i`-th element is the `(i % 8)`-th bit of the
+/// `(i / 8)`-th byte.
+pub fn vecbool_to_vecu8(v: &[bool]) -> Vec<u8> {
+    let mut res: Vec<u8> = vec![0u8; v.len().div_ceil(8)];
+    for (i, &b) in v.iter().enumerate() {
+        if b {
+            res[i / 8] ^= 1u8 << (i & 7);
+        }
+    }
+    res
+}
+
+/// Unpacks bytes packed by `vecbool_to_vecu8`.
+pub fn vecu8_to_vecbool(v: &[u8], dim: usize) -> Vec<bool> {
+    (0..dim).map(|i| v[i / 8] & (1u8 << (i & 7)) != 0).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// A file name unique to the test and the process, so that tests can run in parallel.
+    fn test_file(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("tetris20g_ai_test_{}_{}.bin", name, process::id()))
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let path = test_file("dataset");
+        let filename = path.to_str().unwrap();
+        let feature0 = vec![true, false, true, true, false, false, false, false, true, true];
+        let feature1 = vec![false; 10];
+
+        let mut writer = DatasetWriter::create(filename, DatasetKind::Pairwise, 1, 10, true).unwrap();
+        for step in 0..3 {
+            let metadata = PairMetadata {
+                source: format!("log{}.txt", step % 2),
+                step,
+                rank: step as u32 + 1,
+            };
+            writer
+                .write_pair(&vecbool_to_vecu8(&feature0), &vecbool_to_vecu8(&feature1), Some(&metadata))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = DatasetReader::open(filename).unwrap();
+        assert_eq!(reader.header().records, 3);
+        assert_eq!(reader.header().dimension, 10);
+        assert_eq!(reader.pair(2).unwrap(), (feature0, feature1));
+        assert_eq!(reader.metadata(1).unwrap().source, "log1.txt");
+        assert_eq!(reader.metadata(2).unwrap().rank, 3);
+        assert_eq!(reader.sources().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_and_read_list() {
+        let path = test_file("dataset_list");
+        let filename = path.to_str().unwrap();
+        let lists: Vec<Vec<Vec<bool>>> = vec![
+            vec![vec![true; 9], vec![false; 9]],
+            vec![vec![false; 9], vec![true; 9], vec![true, false, true, false, true, false, true, false, true]],
+        ];
+
+        let mut writer = DatasetWriter::create(filename, DatasetKind::Listwise, 1, 9, false).unwrap();
+        for (choice, list) in lists.iter().enumerate() {
+            let packed: Vec<Vec<u8>> = list.iter().map(|f| vecbool_to_vecu8(f)).collect();
+            writer.write_list(&packed, choice as u32, None).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = DatasetReader::open(filename).unwrap();
+        assert_eq!(reader.header().kind, DatasetKind::Listwise);
+        assert_eq!(reader.list(1).unwrap(), (lists[1].clone(), 1));
+        assert_eq!(reader.list(0).unwrap(), (lists[0].clone(), 0));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,97 +1,514 @@
 //! Module for generating a dataset for optimizing policy parameters.
+//!
+//! Log files are read in a streaming manner, and generated records are written into shards of a
+//! bounded size. Each shard is a dataset file of the format in `dataset` module. Which shards
+//! exist and how many records each of them has is recorded in a manifest file
+//! `<prefix>.manifest.json`.
+//!
+//! At each log step, the field the human actually reached `horizon` pieces later is compared
+//! with the alternative fields reachable with the same pieces. Either pairs of the human field
+//! and each alternative (pairwise), or a whole candidate list with the index of the human field
+//! (listwise) is written.
 
-use std::fs::OpenOptions;
-use std::io::{self, Write};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use dataset::{vecbool_to_vecu8, DatasetKind, DatasetReader, DatasetWriter, PairMetadata};
+use dataset::HEADER_SIZE;
 use enumeration::enumerate_multi;
-use logger::load_log_file;
-use regressor::extract_feature;
-use regressor::LinearRegressor;
+use logger::{read_log_file, LogInfo};
+use regressor::{extract_feature, FEATURE_EXTRACTOR_ID};
+use regressor::{load_model, ValueModel};
 use core::EMPTY_FIELD;
 use core::fix_piece;
 use core::Field;
-use rand;
-use rand::distributions::{IndependentSample, Range};
-
-fn vecbool_to_vecu8(v: &Vec<bool>) -> Vec<u8> {
-    let len = v.len();
-    let mut res: Vec<u8> = vec![0u8; (len + 7) / 8];
-    for i in 0..len {
-        if v[i] {
-            res[i / 8] ^= 1u8 << (i & 7);
+use pair_sampler::{PairSampler, SamplingSummary, Scored};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use serde_json;
+
+/// Which part of a dataset a shard belongs to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Split {
+    Train,
+    Validation,
+}
+
+impl fmt::Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Split::Train => write!(f, "train"),
+            Split::Validation => write!(f, "val"),
+        }
+    }
+}
+
+/// The unit of the train/validation split. The split is decided by a hash of the unit,
+/// so the same input always goes to the same side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitBy {
+    /// All steps of a log file go to the same side.
+    Game,
+    /// Each step is assigned independently.
+    Step,
+}
+
+impl FromStr for SplitBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SplitBy, String> {
+        match s {
+            "game" => Ok(SplitBy::Game),
+            "step" => Ok(SplitBy::Step),
+            _ => Err(format!("unknown split unit: {} (expected `game` or `step`)", s)),
+        }
+    }
+}
+
+/// What to do when the output dataset already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Refuse to write anything.
+    Fail,
+    /// Remove the existing shards and write a new dataset.
+    Overwrite,
+    /// Keep the existing shards and add new shards after them.
+    Append,
+}
+
+impl FromStr for WriteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<WriteMode, String> {
+        match s {
+            "fail" => Ok(WriteMode::Fail),
+            "overwrite" => Ok(WriteMode::Overwrite),
+            "append" => Ok(WriteMode::Append),
+            _ => Err(format!(
+                "unknown write mode: {} (expected `fail`, `overwrite` or `append`)",
+                s
+            )),
         }
     }
-    res
 }
 
-/// Output a dataset file for a given log file.
-/// Because the number of data can be huge, we can drop some of data specifying `drop_rate`.
-/// ### Args:
-/// * input: Input log file name. (e.g., `dataset/20171101-000000.txt`)
-/// * output: Output binary file name.
-/// * drop_rate: Rate for drop. This should be between 0.0 and 1.0.
-/// * weights_file: If None, drop is uniformly done for all candidates at random. Otherwise,
-/// weights file is loaded and bottom `drop_rate` of candidates in terms of value scores are
-/// dropped.
-pub fn generate_dataset(input: &str, output: &str, drop_rate: f64, weights_file: Option<String>) {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(output)
-        .unwrap();
+/// Information of a single shard file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShardInfo {
+    /// File name relative to the directory of the manifest.
+    pub file: String,
+    pub split: Split,
+    /// The number of pairs or lists.
+    #[serde(alias = "pairs")]
+    pub records: usize,
+}
 
-    let log_infos = load_log_file(input);
-    let uniform = Range::new(0f64, 1f64);
-    let mut rng = rand::thread_rng();
+/// Summary of a whole dataset.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    #[serde(default)]
+    pub kind: DatasetKind,
+    pub dimension: usize,
+    pub inputs: Vec<String>,
+    pub shards: Vec<ShardInfo>,
+    /// Summary of each generation run which wrote into this dataset.
+    #[serde(default)]
+    pub sampling: Vec<SamplingSummary>,
+}
 
-    let mut regressor = LinearRegressor::new();
-    if let Some(ref file) = weights_file {
-        regressor.load(&file);
+impl Manifest {
+    pub fn load(filename: &str) -> Manifest {
+        let mut file = OpenOptions::new().read(true).open(filename).unwrap();
+        let mut all = String::new();
+        file.read_to_string(&mut all).unwrap();
+        serde_json::from_str(&all).unwrap()
+    }
+
+    pub fn save(&self, filename: &str) {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(filename)
+            .unwrap();
+        file.write_all(serde_json::to_string_pretty(&self).unwrap().as_bytes())
+            .unwrap();
+    }
+
+    /// Returns the total number of records in a given split.
+    pub fn records(&self, split: Split) -> usize {
+        self.shards
+            .iter()
+            .filter(|shard| shard.split == split)
+            .map(|shard| shard.records)
+            .sum()
+    }
+
+    /// Checks that every shard in `dir` exists and agrees with the manifest, and returns the
+    /// problems found.
+    pub fn verify(&self, dir: &Path) -> Vec<String> {
+        let mut problems = vec![];
+        for shard in self.shards.iter() {
+            let path = dir.join(&shard.file);
+            let reader = match DatasetReader::open(path.to_str().unwrap()) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    problems.push(format!("{}: {}", shard.file, err));
+                    continue;
+                }
+            };
+            let header = reader.header();
+            if header.kind != self.kind || header.dimension as usize != self.dimension {
+                problems.push(format!(
+                    "{}: {:?} with dimension {}, but the manifest says {:?} with dimension {}",
+                    shard.file, header.kind, header.dimension, self.kind, self.dimension
+                ));
+            }
+            if header.records != shard.records as u64 {
+                problems.push(format!(
+                    "{}: {} records, but the manifest says {}",
+                    shard.file, header.records, shard.records
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// Options of `generate_dataset`.
+pub struct DatasetOptions {
+    /// Input log file names. (e.g., `dataset/20171101-000000.txt`)
+    pub inputs: Vec<String>,
+    /// Output path prefix. Shards are named `<prefix>-<split>-<index>.bin`.
+    pub output: String,
+    /// Maximum size of a shard in bytes.
+    pub max_shard_bytes: usize,
+    /// Ratio of data put into the validation split.
+    pub validation_ratio: f64,
+    pub split_by: SplitBy,
+    pub write_mode: WriteMode,
+    pub kind: DatasetKind,
+    /// The number of pieces from a log step to the compared fields. Candidates are enumerated
+    /// for all of these pieces, so the cost grows exponentially. The default is 2.
+    pub horizon: usize,
+    /// Strategy for selecting alternatives paired with the human choice.
+    pub sampler: Box<dyn PairSampler>,
+    /// Model file (linear weights or MLP) for value scores. Required by some samplers.
+    pub weights_file: Option<String>,
+    /// Seed for random selection in samplers.
+    pub seed: u32,
+    /// If true, source log, step and rank of the alternative are stored for each record.
+    pub metadata: bool,
+}
+
+/// Returns the manifest file name for an output prefix.
+pub fn manifest_file_name(output: &str) -> String {
+    format!("{}.manifest.json", output)
+}
+
+/// FNV-1a hash, which is stable across platforms and runs unlike `DefaultHasher`.
+fn stable_hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Returns the random state for a step of a log, which depends only on the seed and the step, so
+/// that the result does not depend on the order or the set of inputs.
+fn step_rng(seed: u32, game: &str, step: i32) -> XorShiftRng {
+    let mut rng: XorShiftRng =
+        SeedableRng::from_seed([seed, stable_hash(game) as u32, step as u32, 0x5851_f42d]);
+    // the first outputs of xorshift hardly depend on the middle words of the seed
+    for _ in 0..8 {
+        rng.next_u32();
+    }
+    rng
+}
+
+fn split_of(key: &str, validation_ratio: f64) -> Split {
+    if ((stable_hash(key) % 1_000_000) as f64) < validation_ratio * 1_000_000.0 {
+        Split::Validation
+    } else {
+        Split::Train
+    }
+}
+
+/// Writes records into shards of a bounded size.
+struct ShardWriter {
+    dir: String,
+    prefix: String,
+    split: Split,
+    kind: DatasetKind,
+    dim: usize,
+    metadata: bool,
+    max_bytes: usize,
+    bytes: usize,
+    next_index: usize,
+    file: Option<DatasetWriter>,
+    shards: Vec<ShardInfo>,
+}
+
+impl ShardWriter {
+    fn new(output: &str, split: Split, options: &DatasetOptions, dim: usize, next_index: usize)
+        -> ShardWriter
+    {
+        let path = Path::new(output);
+        let dir = path.parent().map_or(String::new(), |p| p.to_string_lossy().into_owned());
+        let prefix = path.file_name().unwrap().to_string_lossy().into_owned();
+        ShardWriter {
+            dir,
+            prefix,
+            split,
+            kind: options.kind,
+            dim,
+            metadata: options.metadata,
+            max_bytes: options.max_shard_bytes,
+            bytes: 0,
+            next_index,
+            file: None,
+            shards: vec![],
+        }
+    }
+
+    /// Opens a new shard if a record of `bytes` bytes does not fit in the current one.
+    /// Metadata is not counted, as it is much smaller than feature vectors.
+    fn reserve(&mut self, bytes: usize) {
+        let empty = self.shards.last().is_none_or(|shard| shard.records == 0);
+        if self.file.is_none() || (!empty && self.bytes + bytes > self.max_bytes) {
+            self.open_next();
+        }
+        self.bytes += bytes;
+        self.shards.last_mut().unwrap().records += 1;
+    }
+
+    fn write_pair(&mut self, feature0: &[u8], feature1: &[u8], metadata: &PairMetadata) {
+        self.reserve(feature0.len() + feature1.len());
+        let file = self.file.as_mut().unwrap();
+        file.write_pair(feature0, feature1, Some(metadata)).unwrap();
+    }
+
+    fn write_list(&mut self, features: &[Vec<u8>], choice: u32, metadata: &PairMetadata) {
+        self.reserve(8 + features.iter().map(|f| f.len()).sum::<usize>());
+        let file = self.file.as_mut().unwrap();
+        file.write_list(features, choice, Some(metadata)).unwrap();
+    }
+
+    fn open_next(&mut self) {
+        self.finish();
+        let name = format!("{}-{}-{:05}.bin", self.prefix, self.split, self.next_index);
+        let path = Path::new(&self.dir).join(&name);
+        let file = DatasetWriter::create(
+            path.to_str().unwrap(),
+            self.kind,
+            FEATURE_EXTRACTOR_ID,
+            self.dim,
+            self.metadata,
+        ).unwrap();
+        self.file = Some(file);
+        self.bytes = HEADER_SIZE as usize;
+        self.shards.push(ShardInfo {
+            file: name,
+            split: self.split,
+            records: 0,
+        });
+        self.next_index += 1;
+    }
+
+    fn finish(&mut self) {
+        if let Some(file) = self.file.take() {
+            file.finish().unwrap();
+        }
+    }
+}
+
+/// Prepares the output according to the write mode, and returns the manifest to be extended.
+fn prepare_output(options: &DatasetOptions, dim: usize) -> Manifest {
+    let manifest_file = manifest_file_name(&options.output);
+    let exists = Path::new(&manifest_file).exists();
+    let dir = Path::new(&options.output).parent().map(|p| p.to_path_buf());
+    if let Some(ref dir) = dir {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir).unwrap();
+        }
+    }
+
+    let empty = Manifest {
+        kind: options.kind,
+        dimension: dim,
+        inputs: vec![],
+        shards: vec![],
+        sampling: vec![],
+    };
+    if !exists {
+        return empty;
+    }
+    let manifest = Manifest::load(&manifest_file);
+    match options.write_mode {
+        WriteMode::Fail => panic!(
+            "{} already exists. Specify the overwrite or append mode.",
+            manifest_file
+        ),
+        WriteMode::Overwrite => {
+            for shard in manifest.shards.iter() {
+                let path = Path::new(&options.output).with_file_name(&shard.file);
+                if path.exists() {
+                    fs::remove_file(path).unwrap();
+                }
+            }
+            empty
+        }
+        WriteMode::Append => {
+            assert_eq!(manifest.dimension, dim, "dimension of {} differs", manifest_file);
+            assert_eq!(manifest.kind, options.kind, "kind of {} differs", manifest_file);
+            manifest
+        }
+    }
+}
+
+fn next_shard_index(manifest: &Manifest, split: Split) -> usize {
+    manifest.shards.iter().filter(|shard| shard.split == split).count()
+}
+
+/// Returns the field the human reached after placing the pieces of `window` and the alternative
+/// fields reachable with the same pieces. `window` consists of consecutive log entries, and its
+/// length is the horizon. Alternatives are sorted by value scores if a model is given, and are
+/// in the enumeration order otherwise.
+fn candidate_fields(window: &VecDeque<LogInfo>, regressor: Option<&dyn ValueModel>)
+    -> (Scored, Vec<Scored>)
+{
+    let first = &window[0];
+    let last = &window[window.len() - 1];
+    let pieces: Vec<u8> = window.iter().map(|e| e.decided.piece_type).collect();
+    let (best, _) = fix_piece(&last.field, &last.decided);
+    let mut candidates = enumerate_multi(&first.field, &pieces);
+    candidates.retain(|e| e[0].last_state != first.decided);
+
+    let score = |field: Field| Scored {
+        field,
+        value: regressor.map(|regressor| regressor.predict(&field)),
+    };
+    let mut candidates: Vec<Scored> =
+        candidates.iter().map(|e| score(e[e.len() - 1].new_field)).collect();
+    if regressor.is_some() {
+        candidates.sort_by(|x, y| y.value.partial_cmp(&x.value).unwrap());
+    }
+    (score(best), candidates)
+}
+
+/// Output a sharded dataset for given log files.
+/// Because the number of data can be huge, alternatives compared with the human choice are
+/// selected by `options.sampler`. Log files are read one entry at a time, so memory usage does
+/// not depend on their sizes. The result is deterministic for a given seed.
+pub fn generate_dataset(options: &DatasetOptions) -> Manifest {
+    assert!(options.horizon >= 1);
+    let regressor = options.weights_file.as_ref().map(|file| load_model(file));
+    if options.sampler.needs_model() && regressor.is_none() {
+        panic!("sampler `{}` requires a weights file", options.sampler.name());
     }
 
     let dim = extract_feature(&EMPTY_FIELD).len();
     println!("dimension = {}", dim);
 
-    for idx in 0..(log_infos.len() - 1) {
-        print!("\r{}", idx);
-        io::stdout().flush().unwrap();
-        if log_infos[idx].step + 1 != log_infos[idx + 1].step {
-            // non-continuous frames
-            continue;
-        }
+    let mut manifest = prepare_output(options, dim);
+    let mut writers = [Split::Train, Split::Validation].iter().map(|&split| {
+        let index = next_shard_index(&manifest, split);
+        ShardWriter::new(&options.output, split, options, dim, index)
+    }).collect::<Vec<_>>();
+    let mut summary = SamplingSummary::new(options.sampler.name(), options.seed);
 
-        let field = log_infos[idx].field;
-        let current_piece = log_infos[idx].next_piece;
-        let next_piece = log_infos[idx + 1].next_piece;
-        let (best, _) = fix_piece(&log_infos[idx + 1].field, &log_infos[idx + 1].decided);
-        let mut candidates = enumerate_multi(&field, &vec![current_piece, next_piece]);
-        candidates.retain(|e| e[0].last_state != log_infos[idx].decided);
-
-        let candidates: Vec<Field> = if let Some(_) = weights_file {
-            // candidates must be sorted by value scores
-            let mut sorted: Vec<(f32, Field)> = candidates
-                .iter()
-                .map(|e| (regressor.predict(&e[1].new_field), e[1].new_field))
-                .collect();
-            sorted.sort_by(|x, y| y.0.partial_cmp(&x.0).unwrap());
-            let mut sorted: Vec<Field> = sorted.iter().map(|e| e.1).collect();
-
-            // the number of elements should be decreased by drop_rate
-            let pos = ((1.0 - drop_rate) * (sorted.len() as f64)) as usize;
-            sorted.split_off(pos);
-            sorted
-        } else {
-            candidates.retain(|_| uniform.ind_sample(&mut rng) > drop_rate);
-            candidates.iter().map(|e| e[1].new_field).collect()
-        };
-
-        for candidate in candidates {
-            let feature0 = extract_feature(&best);
-            let feature0 = vecbool_to_vecu8(&feature0);
-            let feature1 = extract_feature(&candidate);
-            let feature1 = vecbool_to_vecu8(&feature1);
-
-            file.write(&feature0).unwrap();
-            file.write(&feature1).unwrap();
+    for input in options.inputs.iter() {
+        let game = Path::new(input).file_name().unwrap().to_string_lossy().into_owned();
+        let mut window: VecDeque<LogInfo> = VecDeque::new();
+        for (idx, log_info) in read_log_file(input).enumerate() {
+            print!("\r{}: {}", game, idx);
+            io::stdout().flush().unwrap();
+            if window.back().is_some_and(|prev| prev.step + 1 != log_info.step) {
+                // non-continuous frames
+                window.clear();
+            }
+            window.push_back(log_info);
+            if window.len() < options.horizon {
+                continue;
+            }
+            if window.len() > options.horizon {
+                window.pop_front();
+            }
+            let step = window[0].step;
+
+            let split = match options.split_by {
+                SplitBy::Game => split_of(&game, options.validation_ratio),
+                SplitBy::Step => split_of(&format!("{}:{}", game, step), options.validation_ratio),
+            };
+            let writer = &mut writers[if split == Split::Train { 0 } else { 1 }];
+
+            let mut rng = step_rng(options.seed, &game, step);
+            let (best, candidates) = candidate_fields(&window, regressor.as_deref());
+            let selected = options.sampler.sample(&best, &candidates, &mut rng);
+
+            let feature0 = vecbool_to_vecu8(&extract_feature(&best.field));
+            match options.kind {
+                DatasetKind::Pairwise => {
+                    for &rank in selected.iter() {
+                        let feature1 = vecbool_to_vecu8(&extract_feature(&candidates[rank].field));
+                        let metadata = PairMetadata {
+                            source: game.clone(),
+                            step,
+                            rank: rank as u32,
+                        };
+                        writer.write_pair(&feature0, &feature1, &metadata);
+                    }
+                }
+                // a list without alternatives does not teach anything
+                DatasetKind::Listwise if selected.is_empty() => {}
+                DatasetKind::Listwise => {
+                    let mut features: Vec<Vec<u8>> = selected
+                        .iter()
+                        .map(|&rank| vecbool_to_vecu8(&extract_feature(&candidates[rank].field)))
+                        .collect();
+                    // the human choice is put at a random position
+                    let choice = rng.gen_range(0, features.len() + 1);
+                    features.insert(choice, feature0);
+                    let metadata = PairMetadata {
+                        source: game.clone(),
+                        step,
+                        rank: choice as u32,
+                    };
+                    writer.write_list(&features, choice as u32, &metadata);
+                }
+            }
+            summary.add_step(&game, candidates.len(), selected.len());
         }
+        println!();
+        manifest.inputs.push(input.clone());
+    }
+
+    for writer in writers.iter_mut() {
+        writer.finish();
+        manifest.shards.append(&mut writer.shards);
+    }
+    manifest.sampling.push(summary);
+    manifest.save(&manifest_file_name(&options.output));
+    manifest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_is_deterministic() {
+        let splits: Vec<Split> = (0..1000).map(|i| split_of(&format!("game{}", i), 0.2)).collect();
+        let again: Vec<Split> = (0..1000).map(|i| split_of(&format!("game{}", i), 0.2)).collect();
+        assert_eq!(splits, again);
+        let n = splits.iter().filter(|&&split| split == Split::Validation).count();
+        assert!(n > 150 && n < 250);
+
+        // the first draws differ between steps
+        let draws: Vec<u32> = (0..100).map(|step| step_rng(0, "game", step).next_u32()).collect();
+        assert!(draws[1..].iter().any(|&draw| draw != draws[0]));
+        assert_eq!(step_rng(0, "game", 5).next_u32(), draws[5]);
     }
 }
@@ -39,7 +39,10 @@ pub fn enumerate_single(field: &Field, piece_type: u8) -> Vec<FixedInfo> {
         }
     }
 
-    result.into_iter().collect()
+    // sort the result so that the order does not depend on the hash state
+    let mut result: Vec<FixedInfo> = result.into_iter().collect();
+    result.sort();
+    result
 }
 
 /// Enumerates possible moves in multiple steps.
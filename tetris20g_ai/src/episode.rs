@@ -0,0 +1,104 @@
+//! Module for running closed-loop episodes of an agent without any display.
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use agent::Agent;
+use core::{self, Field, ScoreInfo};
+use utility;
+
+/// Result of a single episode.
+pub struct EpisodeResult {
+    pub seed: u32,
+    pub score_info: ScoreInfo,
+    /// This is false if the episode was truncated by the step limit.
+    pub ended: bool,
+    /// The maximum stack height after a placement.
+    pub max_height: usize,
+    /// Seconds the agent took to decide a move, on average.
+    pub time_per_move: f64,
+}
+
+/// Runs a single episode from a given field until the agent cannot place a piece or
+/// `max_steps` pieces are placed. The seed of the result is 0 since the pieces are given.
+pub fn run_episode(agent: &mut dyn Agent, field: &Field, pieces: &[u8], max_steps: Option<usize>)
+    -> EpisodeResult
+{
+    let mut field = *field;
+    let mut score_info = ScoreInfo::new();
+    let mut max_height = utility::stack_height(&field);
+    let mut seconds = 0.0;
+    let mut ended = true;
+    for step in 0.. {
+        if max_steps.is_some_and(|max_steps| step >= max_steps) {
+            ended = false;
+            break;
+        }
+        let next_piece = pieces[step % pieces.len()];
+        let next2_piece = pieces[(step + 1) % pieces.len()];
+        let start = Instant::now();
+        let prediction = agent.predict(&field, next_piece, next2_piece);
+        seconds += start.elapsed().as_secs_f64();
+        let state = match prediction {
+            None => break,
+            Some(state) => state,
+        };
+        let (new_field, del) = core::fix_piece(&field, &state);
+        field = new_field;
+        score_info.update(del);
+        max_height = max_height.max(utility::stack_height(&field));
+    }
+    let time_per_move = seconds / score_info.steps.max(1) as f64;
+    EpisodeResult { seed: 0, score_info, ended, max_height, time_per_move }
+}
+
+/// Runs a seeded episode in the same manner as `closed_loop`: pieces are generated by
+/// `utility::generate_pieces` and `lines` lines are initially filled, both with `seed`.
+pub fn run_seeded_episode(agent: &mut dyn Agent, seed: u32, lines: usize, max_steps: Option<usize>)
+    -> EpisodeResult
+{
+    let field = utility::filled_field(lines, Some(seed));
+    let pieces = utility::generate_pieces(100000, Some(seed));
+    EpisodeResult { seed, ..run_episode(agent, &field, &pieces, max_steps) }
+}
+
+/// Applies `f` to every task using `threads` worker threads, and returns results in the
+/// same order as `tasks`.
+pub fn run_parallel<T, R, F>(tasks: &[T], threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = Mutex::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..tasks.len()).map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let idx = {
+                    let mut next = next.lock().unwrap();
+                    *next += 1;
+                    *next - 1
+                };
+                if idx >= tasks.len() {
+                    break;
+                }
+                let result = f(&tasks[idx]);
+                results.lock().unwrap()[idx] = Some(result);
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().map(|r| r.unwrap()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_parallel_keeps_order() {
+        let tasks: Vec<u32> = (0..100).collect();
+        let results = run_parallel(&tasks, 4, |&x| x * 2);
+        assert_eq!(results, tasks.iter().map(|x| x * 2).collect::<Vec<u32>>());
+    }
+}
@@ -0,0 +1,581 @@
+//! Module for fumen, the text encoding of fields and piece placements which players use to share
+//! positions and sequences, e.g., `v115@vhAAgH` is a page of the empty field.
+//!
+//! Only version 1.15 is supported. A fumen field has 23 rows and a garbage row below them, and
+//! its bottom `HEIGHT` rows correspond to a `Field`. The garbage row is not represented in pages.
+//! Pieces in fumen are positioned by their rotation center in the Super Rotation System, so they
+//! are translated to and from `PieceState` by the cells they occupy, and the rotation of a state
+//! follows `core::shape`. For example, the T of fumen pointing up is the rotation 2 of ARS.
+use core::{cycle, fix_piece, shape, Field, PieceState, Position, EMPTY_FIELD, HEIGHT, WIDTH};
+use replay::Replay;
+
+const ENCODE_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// Cells of fumen pieces by their numbers.
+const PIECES: &[u8] = b".ILOZTJSX";
+/// The number of rows of a fumen field without the garbage row.
+const FUMEN_HEIGHT: usize = 23;
+/// The number of cells of a fumen field with the garbage row.
+const FUMEN_CELLS: usize = (FUMEN_HEIGHT + 1) * WIDTH;
+const MAX_COMMENT_LENGTH: usize = 4095;
+/// Rotations in the order of their numbers in fumen.
+const REVERSE: u32 = 0;
+const RIGHT: u32 = 1;
+const SPAWN: u32 = 2;
+const LEFT: u32 = 3;
+
+type FumenField = [u8; FUMEN_CELLS];
+
+/// A page of a fumen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// The field before the piece is placed.
+    pub field: Field,
+    /// The piece shown on the field, if any.
+    pub piece: Option<PieceState>,
+    /// Whether the piece is fixed on the field of the next page, clearing filled lines.
+    pub lock: bool,
+    /// The comment shown on the page. It is kept until another one is given.
+    pub comment: String,
+}
+
+impl Page {
+    /// Returns a page placing a piece on a field.
+    pub fn placement(field: &Field, state: &PieceState) -> Page {
+        Page { field: *field, piece: Some(state.clone()), lock: true, comment: String::new() }
+    }
+}
+
+/// Decodes a fumen such as `v115@vhAAgH`. Anything before the version, e.g., a URL, is ignored.
+pub fn decode(fumen: &str) -> Result<Vec<Page>, String> {
+    let start = ["v115@", "m115@", "d115@"]
+        .iter()
+        .filter_map(|prefix| fumen.find(prefix))
+        .min()
+        .ok_or_else(|| String::from("not a fumen of version 1.15"))?;
+    let digits = fumen[start + 5..]
+        .trim()
+        .bytes()
+        .filter(|&c| c != b'?')
+        .map(|c| {
+            let digit = ENCODE_TABLE.iter().position(|&d| d == c);
+            digit.map(|d| d as u32).ok_or_else(|| format!("invalid character {}", c as char))
+        })
+        .collect::<Result<Vec<u32>, String>>()?;
+    let mut reader = Reader { digits, pos: 0 };
+
+    let mut pages = vec![];
+    let mut prev = [b'.'; FUMEN_CELLS];
+    let mut repeat = 0;
+    let mut comment = String::new();
+    while reader.pos < reader.digits.len() {
+        let mut current = prev;
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut index = 0;
+            while index < FUMEN_CELLS {
+                let value = reader.poll(2)?;
+                let (diff, count) = ((value / 240) as usize, (value % 240 + 1) as usize);
+                if diff > 16 || index + count > FUMEN_CELLS {
+                    return Err(String::from("invalid field"));
+                }
+                for cell in current[index..index + count].iter_mut() {
+                    let piece = (piece_number(*cell) + diff).checked_sub(8);
+                    *cell = match piece.and_then(|piece| PIECES.get(piece)) {
+                        Some(&cell) => cell,
+                        None => return Err(String::from("invalid field")),
+                    };
+                }
+                if count == FUMEN_CELLS && diff == 8 {
+                    repeat = reader.poll(1)?;
+                }
+                index += count;
+            }
+        }
+
+        let action = reader.poll(3)?;
+        let (piece, rotation, pos) = (action % 8, action / 8 % 4, action / 32 % 240);
+        let flags = action / (32 * 240);
+        let (rise, mirror) = (flags & 1 != 0, flags & 2 != 0);
+        let lock = flags & 16 == 0;
+        if flags & 8 != 0 {
+            let len = reader.poll(2)? as usize;
+            let mut escaped = String::new();
+            for _ in 0..len.div_ceil(4) {
+                let mut value = reader.poll(5)?;
+                for _ in 0..4 {
+                    escaped.push((value % 96 + 32) as u8 as char);
+                    value /= 96;
+                }
+            }
+            escaped.truncate(len);
+            comment = unescape(&escaped);
+        }
+
+        let (piece, cells) = match piece {
+            0 => (None, vec![]),
+            _ => {
+                let cells = fumen_cells(piece, rotation, pos)?;
+                (Some(state_from_cells(PIECES[piece as usize], &cells)?), cells)
+            }
+        };
+        let field = from_fumen_field(&current)?;
+        pages.push(Page { field, piece, lock, comment: comment.clone() });
+
+        if lock {
+            for &cell in cells.iter() {
+                current[cell] = PIECES[(action % 8) as usize];
+            }
+            clear_lines(&mut current);
+            if rise {
+                current.copy_within(WIDTH.., 0);
+                for cell in current[FUMEN_HEIGHT * WIDTH..].iter_mut() {
+                    *cell = b'.';
+                }
+            }
+            if mirror {
+                for row in current[..FUMEN_HEIGHT * WIDTH].chunks_mut(WIDTH) {
+                    row.reverse();
+                }
+            }
+        }
+        prev = current;
+    }
+    if pages.is_empty() {
+        return Err(String::from("no pages"));
+    }
+    Ok(pages)
+}
+
+/// Encodes pages into a fumen.
+pub fn encode(pages: &[Page]) -> String {
+    let mut writer = Writer { digits: vec![] };
+    let mut prev = [b'.'; FUMEN_CELLS];
+    let mut repeat_index: Option<usize> = None;
+    let mut comment = String::new();
+    for (i, page) in pages.iter().enumerate() {
+        let mut current = to_fumen_field(&page.field);
+        if current != prev {
+            let diffs: Vec<usize> = current
+                .iter()
+                .zip(prev.iter())
+                .map(|(&c, &p)| piece_number(c) + 8 - piece_number(p))
+                .collect();
+            let mut index = 0;
+            while index < FUMEN_CELLS {
+                let count = diffs[index..].iter().take_while(|&&d| d == diffs[index]).count();
+                writer.push((diffs[index] * 240 + count - 1) as u32, 2);
+                index += count;
+            }
+            repeat_index = None;
+        } else {
+            match repeat_index {
+                Some(index) if writer.digits[index] < 63 => writer.digits[index] += 1,
+                _ => {
+                    writer.push((8 * 240 + FUMEN_CELLS - 1) as u32, 2);
+                    writer.push(0, 1);
+                    repeat_index = Some(writer.digits.len() - 1);
+                }
+            }
+        }
+
+        let (piece, rotation, pos, cells) = match page.piece {
+            Some(ref state) => to_fumen_piece(state),
+            None => (0, REVERSE, 0, vec![]),
+        };
+        let comment_changed = page.comment != comment;
+        // lock, comment and colorize, which fumen sets on the first page
+        let flags =
+            (!page.lock as u32) << 4 | (comment_changed as u32) << 3 | ((i == 0) as u32) << 2;
+        writer.push(((flags * 240 + pos) * 4 + rotation) * 8 + piece, 3);
+        if comment_changed {
+            let escaped = escape(&page.comment);
+            let escaped = &escaped.as_bytes()[..escaped.len().min(MAX_COMMENT_LENGTH)];
+            writer.push(escaped.len() as u32, 2);
+            for chunk in escaped.chunks(4) {
+                let value = chunk.iter().rev().fold(0, |value, &c| value * 96 + (c - 32) as u32);
+                writer.push(value, 5);
+            }
+            comment = page.comment.clone();
+        }
+
+        if page.lock {
+            for &cell in cells.iter() {
+                current[cell] = PIECES[piece as usize];
+            }
+            clear_lines(&mut current);
+        }
+        prev = current;
+    }
+
+    let data: Vec<u8> = writer.digits.iter().map(|&d| ENCODE_TABLE[d as usize]).collect();
+    let data = String::from_utf8(data).unwrap();
+    format!("v115@{}", break_data(&data))
+}
+
+/// Breaks long data by `?` as fumen does: after the first 42 characters and then every 47.
+fn break_data(data: &str) -> String {
+    let head = &data[..data.len().min(42)];
+    let tails: Vec<&str> = data.as_bytes()[head.len()..]
+        .chunks(47)
+        .map(|chunk| ::std::str::from_utf8(chunk).unwrap())
+        .collect();
+    if tails.is_empty() {
+        String::from(head)
+    } else {
+        format!("{}?{}", head, tails.join("?"))
+    }
+}
+
+/// Encodes placements from a field, a page for each piece.
+pub fn encode_placements(field: &Field, states: &[PieceState]) -> String {
+    let mut field = *field;
+    let pages: Vec<Page> = states
+        .iter()
+        .map(|state| {
+            let page = Page::placement(&field, state);
+            field = fix_piece(&field, state).0;
+            page
+        })
+        .collect();
+    encode(&pages)
+}
+
+/// Encodes the placements of a replay. Returns an error if the replay cannot be reproduced.
+pub fn encode_replay(replay: &Replay) -> Result<String, String> {
+    let frames = replay.frames()?;
+    let pages: Vec<Page> = frames
+        .windows(2)
+        .filter(|frames| frames[1].piece > frames[0].piece)
+        .map(|frames| Page::placement(&frames[0].field, &frames[0].state))
+        .collect();
+    if pages.is_empty() {
+        let field = frames[0].field;
+        return Ok(encode(&[Page { field, piece: None, lock: true, comment: String::new() }]));
+    }
+    Ok(encode(&pages))
+}
+
+/// Returns the position of the first page, with the pieces of the pages as the queue.
+pub fn position(pages: &[Page]) -> Position {
+    Position {
+        field: pages[0].field,
+        queue: pages.iter().filter_map(|page| page.piece.as_ref()).map(|p| p.piece_type).collect(),
+        hold: None,
+    }
+}
+
+struct Reader {
+    digits: Vec<u32>,
+    pos: usize,
+}
+
+impl Reader {
+    /// Reads a value of `n` digits, the lowest first.
+    fn poll(&mut self, n: usize) -> Result<u32, String> {
+        if self.pos + n > self.digits.len() {
+            return Err(String::from("unexpected end of data"));
+        }
+        let digits = &self.digits[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(digits.iter().rev().fold(0, |value, &d| value * 64 + d))
+    }
+}
+
+struct Writer {
+    digits: Vec<u32>,
+}
+
+impl Writer {
+    fn push(&mut self, mut value: u32, n: usize) {
+        for _ in 0..n {
+            self.digits.push(value % 64);
+            value /= 64;
+        }
+    }
+}
+
+fn piece_number(cell: u8) -> usize {
+    PIECES.iter().position(|&c| c == cell).unwrap()
+}
+
+fn to_fumen_field(field: &Field) -> FumenField {
+    let mut fumen = [b'.'; FUMEN_CELLS];
+    let offset = (FUMEN_HEIGHT - HEIGHT) * WIDTH;
+    for (i, row) in field.iter().enumerate() {
+        for (j, &cell) in row.iter().enumerate() {
+            fumen[offset + i * WIDTH + j] = if PIECES.contains(&cell) { cell } else { b'X' };
+        }
+    }
+    fumen
+}
+
+fn from_fumen_field(fumen: &FumenField) -> Result<Field, String> {
+    let offset = (FUMEN_HEIGHT - HEIGHT) * WIDTH;
+    if fumen[..offset].iter().any(|&cell| cell != b'.') {
+        return Err(format!("blocks above the {} rows", HEIGHT));
+    }
+    let mut field = EMPTY_FIELD;
+    for (row, cells) in field.iter_mut().zip(fumen[offset..].chunks(WIDTH)) {
+        row.copy_from_slice(cells);
+    }
+    Ok(field)
+}
+
+fn clear_lines(fumen: &mut FumenField) {
+    let rows: Vec<[u8; WIDTH]> = fumen[..FUMEN_HEIGHT * WIDTH]
+        .chunks(WIDTH)
+        .filter(|row| row.contains(&b'.'))
+        .map(|row| {
+            let mut cells = [b'.'; WIDTH];
+            cells.copy_from_slice(row);
+            cells
+        })
+        .collect();
+    let top = (FUMEN_HEIGHT - rows.len()) * WIDTH;
+    for cell in fumen[..top].iter_mut() {
+        *cell = b'.';
+    }
+    for (i, row) in rows.iter().enumerate() {
+        fumen[top + i * WIDTH..top + (i + 1) * WIDTH].copy_from_slice(row);
+    }
+}
+
+/// Cells of a fumen piece in the spawn rotation, as `(x, y)` with y upward, around its center.
+fn spawn_blocks(piece: u32) -> [(i32, i32); 4] {
+    match PIECES[piece as usize] {
+        b'I' => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+        b'T' => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+        b'O' => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        b'L' => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+        b'J' => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+        b'S' => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+        b'Z' => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+        _ => panic!(),
+    }
+}
+
+fn blocks(piece: u32, rotation: u32) -> [(i32, i32); 4] {
+    let mut blocks = spawn_blocks(piece);
+    for block in blocks.iter_mut() {
+        let (x, y) = *block;
+        *block = match rotation {
+            RIGHT => (y, -x),
+            REVERSE => (-x, -y),
+            LEFT => (-y, x),
+            _ => (x, y),
+        };
+    }
+    blocks
+}
+
+/// The difference between the rotation center and the position encoded in fumen.
+fn center_correction(piece: u32, rotation: u32) -> (i32, i32) {
+    match (PIECES[piece as usize], rotation) {
+        (b'O', LEFT) => (1, -1),
+        (b'O', REVERSE) => (1, 0),
+        (b'O', SPAWN) => (0, -1),
+        (b'I', REVERSE) => (1, 0),
+        (b'I', LEFT) => (0, -1),
+        (b'S', SPAWN) => (0, -1),
+        (b'S', RIGHT) => (-1, 0),
+        (b'Z', SPAWN) => (0, -1),
+        (b'Z', LEFT) => (1, 0),
+        _ => (0, 0),
+    }
+}
+
+/// Returns the indices of the cells of a fumen piece.
+fn fumen_cells(piece: u32, rotation: u32, pos: u32) -> Result<Vec<usize>, String> {
+    let (dx, dy) = center_correction(piece, rotation);
+    let x = (pos as usize % WIDTH) as i32 + dx;
+    let y = (FUMEN_HEIGHT - 1 - pos as usize / WIDTH) as i32 + dy;
+    blocks(piece, rotation)
+        .iter()
+        .map(|&(bx, by)| {
+            let (x, y) = (x + bx, y + by);
+            if x < 0 || x >= WIDTH as i32 || y < 0 || y >= FUMEN_HEIGHT as i32 {
+                return Err(String::from("a piece out of the field"));
+            }
+            Ok((FUMEN_HEIGHT - 1 - y as usize) * WIDTH + x as usize)
+        })
+        .collect()
+}
+
+/// Returns the cells of a state as `(row, column)` in a field, sorted.
+fn state_cells(piece: u8, rotation: usize, x: i32, y: i32) -> Vec<(i32, i32)> {
+    let mut cells = vec![];
+    for (i, row) in shape(piece, rotation).iter().enumerate() {
+        for (j, cell) in row.bytes().enumerate() {
+            if cell == b'#' {
+                cells.push((y + i as i32, x + j as i32));
+            }
+        }
+    }
+    cells
+}
+
+/// Finds the state occupying given fumen cells.
+fn state_from_cells(piece: u8, cells: &[usize]) -> Result<PieceState, String> {
+    let offset = (FUMEN_HEIGHT - HEIGHT) as i32;
+    let mut cells: Vec<(i32, i32)> = cells
+        .iter()
+        .map(|&cell| ((cell / WIDTH) as i32 - offset, (cell % WIDTH) as i32))
+        .collect();
+    cells.sort();
+    for rotation in 0..cycle(piece) {
+        let origin = state_cells(piece, rotation, 0, 0);
+        let (y, x) = (cells[0].0 - origin[0].0, cells[0].1 - origin[0].1);
+        if state_cells(piece, rotation, x, y) == cells {
+            let (x, y) = (x as i8, y as i8);
+            return Ok(PieceState { piece_type: piece, x, y, rotation, first: false });
+        }
+    }
+    Err(format!("{} cannot be placed in this shape", piece as char))
+}
+
+/// Returns the piece number, rotation, position and cell indices of a state in fumen.
+fn to_fumen_piece(state: &PieceState) -> (u32, u32, u32, Vec<usize>) {
+    let piece = piece_number(state.piece_type) as u32;
+    let offset = (FUMEN_HEIGHT - HEIGHT) as i32;
+    let cells: Vec<(i32, i32)> =
+        state_cells(state.piece_type, state.rotation, state.x as i32, state.y as i32)
+            .into_iter()
+            .map(|(row, column)| (column, FUMEN_HEIGHT as i32 - 1 - (row + offset)))
+            .collect();
+    let mut indices: Vec<usize> = cells
+        .iter()
+        .map(|&(x, y)| (FUMEN_HEIGHT - 1 - y as usize) * WIDTH + x as usize)
+        .collect();
+    indices.sort();
+    // the center is one of the cells
+    for &rotation in [SPAWN, RIGHT, REVERSE, LEFT].iter() {
+        let (dx, dy) = center_correction(piece, rotation);
+        for &(x, y) in cells.iter() {
+            let (px, py) = (x - dx, y - dy);
+            if px < 0 || px >= WIDTH as i32 || py < 0 || py >= FUMEN_HEIGHT as i32 {
+                continue;
+            }
+            let pos = (FUMEN_HEIGHT as i32 - 1 - py) as u32 * WIDTH as u32 + px as u32;
+            if let Ok(mut found) = fumen_cells(piece, rotation, pos) {
+                found.sort();
+                if found == indices {
+                    return (piece, rotation, pos, indices);
+                }
+            }
+        }
+    }
+    panic!("{:?} cannot be encoded", state)
+}
+
+/// Escapes a comment in the same way as `escape` of JavaScript.
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for unit in s.encode_utf16() {
+        let safe = |c: u8| c.is_ascii_alphanumeric() || b"@*_+-./".contains(&c);
+        match unit {
+            0..=127 if safe(unit as u8) => escaped.push(unit as u8 as char),
+            0..=255 => escaped += &format!("%{:02X}", unit),
+            _ => escaped += &format!("%u{:04X}", unit),
+        }
+    }
+    escaped
+}
+
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut units = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |from: usize, len: usize| {
+            s.get(from..from + len).and_then(|hex| u16::from_str_radix(hex, 16).ok())
+        };
+        if bytes[i] == b'%' {
+            if bytes.get(i + 1) == Some(&b'u') {
+                if let Some(unit) = hex(i + 2, 4) {
+                    units.push(unit);
+                    i += 6;
+                    continue;
+                }
+            } else if let Some(unit) = hex(i + 1, 2) {
+                units.push(unit);
+                i += 3;
+                continue;
+            }
+        }
+        units.push(bytes[i] as u16);
+        i += 1;
+    }
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::field_from_rows;
+    use enumeration::enumerate_single;
+
+    #[test]
+    fn test_fumen() {
+        let pages = decode("http://fumen.zui.jp/?v115@vhAAgH").unwrap();
+        let empty = Page { field: EMPTY_FIELD, piece: None, lock: true, comment: String::new() };
+        assert_eq!(pages, vec![empty.clone()]);
+        assert_eq!(encode(&pages), "v115@vhAAgH");
+
+        // the T of fumen pointing up at the bottom left is the rotation 2 of ARS
+        let t = PieceState { piece_type: b'T', x: 0, y: 16, rotation: 2, first: false };
+        assert_eq!(encode(&[Page::placement(&EMPTY_FIELD, &t)]), "v115@vhA1OJ");
+        assert_eq!(decode("v115@vhA1OJ").unwrap()[0].piece, Some(t));
+
+        // every rotation of every piece, through fields with cleared lines
+        let mut field = [[b'X'; WIDTH]; HEIGHT];
+        for row in field[..HEIGHT - 4].iter_mut() {
+            *row = [b'.'; WIDTH];
+        }
+        field[HEIGHT - 1][0] = b'.';
+        let mut states = vec![];
+        let mut fields = vec![];
+        let mut page_field = field;
+        for &piece in b"IOSZJLT".iter() {
+            for rotation in 0..cycle(piece) {
+                let state = enumerate_single(&page_field, piece)
+                    .into_iter()
+                    .map(|info| info.last_state)
+                    .filter(|state| state.rotation == rotation)
+                    .max_by_key(|state| (state.y, -state.x))
+                    .unwrap();
+                fields.push(page_field);
+                page_field = fix_piece(&page_field, &state).0;
+                states.push(PieceState { first: false, ..state });
+            }
+        }
+        let fumen = encode_placements(&field, &states);
+        assert!(fumen.contains('?'));
+        let pages = decode(&fumen).unwrap();
+        assert_eq!(pages.iter().map(|page| page.field).collect::<Vec<Field>>(), fields);
+        let decoded: Vec<PieceState> =
+            pages.iter().map(|page| page.piece.clone().unwrap()).collect();
+        assert_eq!(decoded, states);
+
+        let mut pages = vec![empty.clone(), empty.clone(), empty];
+        pages[1].comment = String::from("20G 100% テトリス");
+        pages[2].comment = pages[1].comment.clone();
+        pages[2].lock = false;
+        assert_eq!(decode(&encode(&pages)).unwrap(), pages);
+
+        let data = "A".repeat(90);
+        assert_eq!(break_data(&data[..41]), &data[..41]);
+        assert_eq!(break_data(&data[..42]), &data[..42]);
+        assert_eq!(break_data(&data[..43]), format!("{}?A", &data[..42]));
+        assert_eq!(break_data(&data[..90]), format!("{}?{}?A", &data[..42], &data[..47]));
+        // data of exactly 41 characters is not broken
+        let field = field_from_rows(&[".X.X.X.X..", "X.X.X.X.X."]).unwrap();
+        let page = Page { field, piece: None, lock: true, comment: String::new() };
+        let fumen = encode(::std::slice::from_ref(&page));
+        assert_eq!((fumen.len(), fumen.contains('?')), ("v115@".len() + 41, false));
+        assert_eq!(decode(&fumen).unwrap(), vec![page]);
+
+        assert!(decode("v115@vhA").is_err());
+        assert!(decode("v110@7eHeAgH").is_err());
+    }
+}
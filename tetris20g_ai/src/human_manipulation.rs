@@ -1,6 +1,37 @@
 //! Module for maintaining current game state. This will be used for annotation purpose.
+//!
+//! A game is turn-based by default, i.e., a piece is locked only by the player. If the rules
+//! have a lock delay, `Game::tick` has to be called every frame (1/60 seconds), and a piece is
+//! locked when it stays grounded for the lock delay.
+//!
+//! Placed pieces can be undone. Their log records are removed by retraction records, so a game
+//! can also be practiced from a saved `Position` again and again.
+use std::time::Instant;
 use core::{Field, PieceState, EMPTY_FIELD, Command, new_piece, apply_command, CommandResult};
-use logger::{Logger, LogInfo};
+use core::{Position, Rules, ScoreInfo};
+use input_profile::{Action, InputProfile};
+use logger::{InputEvent, Logger, LogInfo, MAX_RETRACTION};
+use replay::ReplayWriter;
+
+/// Phase of a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The current piece can be manipulated.
+    Active,
+    /// Waiting for the next piece to appear. The value is the remaining frames.
+    Are(u32),
+    /// The next piece could not appear.
+    GameOver,
+}
+
+/// The state of a game before a piece was fixed, to which `Game::undo` goes back.
+struct Snapshot {
+    field: Field,
+    current_piece_id: usize,
+    step: i32,
+    score_info: ScoreInfo,
+    last_decided: Option<PieceState>,
+}
 
 pub struct Game {
     pub field: Field,
@@ -8,93 +39,255 @@ pub struct Game {
     pub piece_array: Vec<u8>,
     pub current_piece_id: usize,
     pub charge: i32,
+    pub rules: Rules,
+    pub profile: InputProfile,
+    pub phase: Phase,
+    pub score_info: ScoreInfo,
+    /// The last state of the previously fixed piece.
+    pub last_decided: Option<PieceState>,
+    /// Frames since the current piece last dropped.
+    lock_frames: u32,
     logger: Option<Logger>,
     prev_log_info: Option<LogInfo>,
+    /// Steps of the records this game has written which can be retracted, the latest last.
+    logged: Vec<i32>,
     step: i32,
+    started: Instant,
+    inputs: Vec<InputEvent>,
+    spawned_at: u64,
+    replay: Option<ReplayWriter>,
+    /// Commands applied to the current piece since it appeared or was reset.
+    commands: Vec<Command>,
+    /// Snapshots before the placed pieces which can be undone, the latest last.
+    history: Vec<Snapshot>,
 }
 
 impl Game {
-    pub fn new(piece_array: Vec<u8>, filename: Option<String>) -> Game {
-        let mut logger = None;
-        if let Some(filename) = filename {
-            logger = Some(Logger::new(&filename));
-        }
+    pub fn new(piece_array: Vec<u8>, logger: Option<Logger>, replay: Option<ReplayWriter>) -> Game {
         Game {
             field: EMPTY_FIELD,
             state: new_piece(piece_array[0]),
             piece_array,
             current_piece_id: 0,
             charge: 0,
+            rules: Rules::default(),
+            profile: InputProfile::default(),
+            phase: Phase::Active,
+            score_info: ScoreInfo::new(),
+            last_decided: None,
+            lock_frames: 0,
             logger: logger,
             prev_log_info: None,
+            logged: vec![],
             step: 0,
+            started: Instant::now(),
+            inputs: vec![],
+            spawned_at: 0,
+            replay,
+            commands: vec![],
+            history: vec![],
         }
     }
 
-    pub fn input(&mut self, key: char) {
+    /// Handles a key press. `key` is a key name as in `InputProfile::bindings`.
+    pub fn input(&mut self, key: &str) {
         let mut dx: i32 = 0;
-        let command = match key {
-            'z' => {
+        let command = match self.profile.action(key) {
+            Some(Action::Left) => {
                 dx = -1;
                 Some(Command::Move(dx as i8, 0))
             }
-            'x' => Some(Command::Fix),
-            'c' => {
+            Some(Action::Right) => {
                 dx = 1;
                 Some(Command::Move(dx as i8, 0))
             }
-            'm' | ',' | '.' => {
-                let rotate = if key == ',' { -1 } else { 1 };
-                let synchro = if self.charge.abs() < 6 {
+            Some(Action::HardLock) => Some(Command::Fix),
+            Some(Action::SoftDrop) => match self.rules.lock_delay {
+                Some(lock_delay) => {
+                    // lock at the next frame
+                    self.lock_frames = self.lock_frames.max(lock_delay.saturating_sub(1));
+                    None
+                }
+                None => Some(Command::Fix),
+            },
+            Some(action @ Action::RotateLeft) | Some(action @ Action::RotateRight) => {
+                let rotate = if action == Action::RotateLeft { -1 } else { 1 };
+                let synchro = if self.charge.abs() < self.profile.das_charge {
                     0
                 } else {
                     self.charge.signum() as i8
                 };
                 Some(Command::Move(synchro, rotate))
             }
-            'r' => {
+            Some(Action::Reset) => {
                 self.state = new_piece(self.state.piece_type);
+                self.commands.clear();
+                self.lock_frames = 0;
                 None
             }
-            'n' => {
+            Some(Action::Discard) => {
                 self.prev_log_info = None;
                 None
             }
-            _ => None,
+            // the inputs of the undone piece are discarded, including this one
+            Some(Action::Undo) => {
+                self.undo();
+                return;
+            }
+            // saving needs a file name, so it is done by the caller
+            Some(Action::SavePosition) => None,
+            None => None,
         };
 
+        // the piece cannot be manipulated before it appears
+        let command = if self.phase == Phase::Active { command } else { None };
+
+        self.inputs.push(InputEvent {
+            time: self.elapsed(),
+            key: String::from(key),
+            command: command.clone(),
+        });
+
         if let Some(command) = command {
-            let res = apply_command(&self.field, &self.state, &command);
-            match res {
-                CommandResult::Moved(next_state, _) => {
-                    if self.state == next_state {
-                        if self.charge * dx <= 0 {
-                            self.charge = dx;
-                        } else {
-                            self.charge += dx;
-                        }
-                    } else {
-                        self.state = next_state;
-                        self.charge = 0;
+            self.apply(command, dx);
+        }
+    }
+
+    /// Advances the game by a frame. This only syncs the log in turn-based games.
+    pub fn tick(&mut self) {
+        self.sync_log();
+        match self.phase {
+            Phase::Active => {
+                if let Some(lock_delay) = self.rules.lock_delay {
+                    self.lock_frames += 1;
+                    if self.lock_frames >= lock_delay {
+                        self.apply(Command::Fix, 0);
                     }
+                }
+            }
+            Phase::Are(frames) if frames > 1 => self.phase = Phase::Are(frames - 1),
+            Phase::Are(_) => self.spawn(),
+            Phase::GameOver => (),
+        }
+    }
 
-                    if let Command::Move(0, _) = command {
-                        self.charge = 0;
+    pub fn is_game_over(&self) -> bool {
+        self.phase == Phase::GameOver
+    }
+
+    fn apply(&mut self, command: Command, dx: i32) {
+        self.commands.push(command.clone());
+        match apply_command(&self.field, &self.state, &command) {
+            CommandResult::Moved(next_state, reset) => {
+                if reset {
+                    self.lock_frames = 0;
+                }
+                if self.state == next_state {
+                    if self.charge * dx <= 0 {
+                        self.charge = dx;
+                    } else {
+                        self.charge += dx;
                     }
+                } else {
+                    self.state = next_state;
+                    self.charge = 0;
                 }
-                CommandResult::Fixed(info) => {
-                    self.state = info.last_state;
-                    self.update_log();
-                    self.field = info.new_field;
-                    self.state = new_piece(self.next_piece().unwrap());
-                    self.current_piece_id += 1;
+
+                if let Command::Move(0, _) = command {
                     self.charge = 0;
-                    self.step += 1;
                 }
-                CommandResult::Ended => {
-                    panic!();
+            }
+            CommandResult::Fixed(info) => {
+                if self.history.len() >= MAX_RETRACTION {
+                    self.history.remove(0);
+                }
+                self.history.push(Snapshot {
+                    field: self.field,
+                    current_piece_id: self.current_piece_id,
+                    step: self.step,
+                    score_info: self.score_info.clone(),
+                    last_decided: self.last_decided.clone(),
+                });
+                self.state = info.last_state;
+                self.update_log();
+                if let Some(ref mut replay) = self.replay {
+                    replay.write_piece(&self.commands);
+                }
+                self.commands.clear();
+                self.field = info.new_field;
+                self.score_info.update(info.del);
+                self.last_decided = Some(self.state.clone());
+                self.state = new_piece(self.next_piece().unwrap());
+                self.current_piece_id += 1;
+                self.charge = 0;
+                self.step += 1;
+                match self.rules.are {
+                    Some(are) if are > 0 => self.phase = Phase::Are(are),
+                    _ => self.spawn(),
                 }
             }
+            CommandResult::Ended => {
+                self.phase = Phase::GameOver;
+                self.finish();
+            }
+        }
+    }
+
+    /// Lets the current piece appear, or ends the game if it is blocked.
+    fn spawn(&mut self) {
+        self.spawned_at = self.elapsed();
+        self.lock_frames = 0;
+        self.phase = match apply_command(&self.field, &self.state, &Command::Move(0, 0)) {
+            CommandResult::Ended => Phase::GameOver,
+            _ => Phase::Active,
+        };
+        if self.is_game_over() {
+            self.finish();
+        }
+    }
+
+    /// Takes back the last placed piece, also from the log and the replay. Returns false if
+    /// there is no piece to undo.
+    pub fn undo(&mut self) -> bool {
+        let snapshot = match self.history.pop() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+        // the record of the undone piece is still held back unless it was discarded or the game
+        // finished, and otherwise it has to be retracted if it was written by this game, not by
+        // a previous session of a resumed log
+        if self.prev_log_info.take().is_none() && self.logged.last() == Some(&snapshot.step) {
+            self.logged.pop();
+            if let Some(ref mut logger) = self.logger {
+                logger.retract(1);
+            }
+        }
+        if let Some(ref mut replay) = self.replay {
+            replay.retract(snapshot.current_piece_id);
+        }
+
+        self.field = snapshot.field;
+        self.current_piece_id = snapshot.current_piece_id;
+        self.step = snapshot.step;
+        self.score_info = snapshot.score_info;
+        self.last_decided = snapshot.last_decided;
+        self.state = new_piece(self.piece_array[self.current_piece_id]);
+        self.commands.clear();
+        self.inputs.clear();
+        self.charge = 0;
+        self.spawned_at = self.elapsed();
+        self.lock_frames = 0;
+        self.phase = Phase::Active;
+        true
+    }
+
+    /// Returns the current position with the queue of at most `len` pieces from the current one.
+    pub fn position(&self, len: usize) -> Position {
+        let end = self.piece_array.len().min(self.current_piece_id + len);
+        Position {
+            field: self.field,
+            queue: self.piece_array[self.current_piece_id..end].to_vec(),
+            hold: None,
         }
     }
 
@@ -106,18 +299,140 @@ impl Game {
         }
     }
 
-    pub fn update_log(&mut self) {
-        if let Some(ref prev_log_info) = self.prev_log_info {
-            if let Some(ref mut logger) = self.logger {
-                logger.save(prev_log_info);
+    /// Milliseconds from the start of the game.
+    fn elapsed(&self) -> u64 {
+        let elapsed = self.started.elapsed();
+        elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis())
+    }
+
+    /// Writes the last decided piece, which is held back so that it can be discarded or undone,
+    /// and flushes the log. This is also done when the game is dropped.
+    pub fn finish(&mut self) {
+        if let Some(prev_log_info) = self.prev_log_info.take() {
+            self.write_log(&prev_log_info);
+        }
+        if let Some(ref mut logger) = self.logger {
+            logger.flush().unwrap();
+        }
+    }
+
+    fn write_log(&mut self, log_info: &LogInfo) {
+        if let Some(ref mut logger) = self.logger {
+            logger.save(log_info);
+            if self.logged.len() >= MAX_RETRACTION {
+                self.logged.remove(0);
             }
+            self.logged.push(log_info.step);
+        }
+    }
+
+    /// Syncs the written records to the disk if they have not been synced for a while. This is
+    /// done by `tick`, and has to be called periodically in turn-based games.
+    pub fn sync_log(&mut self) {
+        if let Some(ref mut logger) = self.logger {
+            logger.sync_if_due();
+        }
+    }
+
+    pub fn update_log(&mut self) {
+        if let Some(prev_log_info) = self.prev_log_info.take() {
+            self.write_log(&prev_log_info);
         }
         let log_info = LogInfo {
             field: self.field,
             decided: self.state.clone(),
             next_piece: self.next_piece().unwrap(),
             step: self.step,
+            inputs: self.inputs.split_off(0),
+            spawned_at: self.spawned_at,
         };
         self.prev_log_info = Some(log_info);
     }
 }
+
+impl Drop for Game {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use serde_json;
+    use core::{HEIGHT, WIDTH};
+    use logger::{read_log_file, ExistingLog};
+
+    #[test]
+    fn test_real_time_game() {
+        let mut game = Game::new(b"TIOSZ".to_vec(), None, None);
+        game.rules = Rules::real_time(3, 2);
+        for _ in 0..3 {
+            game.tick();
+        }
+        assert_eq!(game.phase, Phase::Are(2));
+        assert_eq!(game.score_info.steps, 1);
+        game.input("x");
+        assert_eq!(game.score_info.steps, 1);
+        game.tick();
+        game.tick();
+        assert_eq!(game.phase, Phase::Active);
+        assert_eq!(game.state.piece_type, b'I');
+
+        let mut game = Game::new(b"TIOSZ".to_vec(), None, None);
+        game.field = [[b'X'; WIDTH]; HEIGHT];
+        game.input("x");
+        assert!(game.is_game_over());
+    }
+
+    #[test]
+    fn test_undo() {
+        let path = env::temp_dir().join("tetris20g_ai_test_undo.txt");
+        let _ = fs::remove_file(&path);
+        let position: Position = serde_json::from_str(
+            r#"{"field": ["XXXX..XXXX"], "queue": "OTIL"}"#
+        ).unwrap();
+        assert_eq!(serde_json::to_string(&position).unwrap(),
+                   r#"{"field":["XXXX..XXXX"],"queue":"OTIL"}"#);
+        {
+            let logger = Logger::without_header(path.to_str().unwrap());
+            let mut game = Game::new(position.queue.clone(), Some(logger), None);
+            game.field = position.field;
+            game.input("x");
+            let field = game.field;
+            game.input("x");
+            game.input("x");
+            assert_eq!(game.score_info.steps, 3);
+            game.input("c");
+            game.input("u");
+            assert!(game.inputs.is_empty());
+            assert!(game.undo());
+            assert_eq!(game.position(4), Position { field, queue: b"TIL".to_vec(), hold: None });
+            assert_eq!(game.score_info.total_lines, 1);
+            game.input("c");
+            game.input("x");
+        }
+        let entries: Vec<LogInfo> = read_log_file(path.to_str().unwrap()).collect();
+        let steps: Vec<i32> = entries.iter().map(|entry| entry.step).collect();
+        assert_eq!(steps, vec![0, 1]);
+        assert_eq!(entries[1].inputs.len(), 2);
+
+        // undoing in a resumed log does not retract the records of the previous session
+        {
+            let logger = Logger::open(path.to_str().unwrap(), None, ExistingLog::Resume).unwrap();
+            let mut game = Game::new(position.queue.clone(), Some(logger), None);
+            game.field = position.field;
+            game.input("x");
+            game.input("x");
+            assert!(game.undo());
+            assert!(game.undo());
+            assert!(!game.undo());
+            game.input("x");
+        }
+        let steps: Vec<i32> = read_log_file(path.to_str().unwrap()).map(|e| e.step).collect();
+        assert_eq!(steps, vec![0, 1, 0]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! Module for key bindings and input parameters of human play.
+//!
+//! An input config file is a JSON object from player names to their profiles, e.g.,
+//! `{"default": {"name": "classic", "bindings": {"z": "left", ...}, "das_charge": 6}}`.
+//! The profile of a player is looked up by the name, then by `default`.
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Read;
+use serde_json;
+
+/// Keys which cannot be bound because the annotation tool uses them.
+pub const RESERVED_KEYS: [&str; 2] = ["q", "enter"];
+
+/// An action of the player.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Left,
+    Right,
+    RotateLeft,
+    RotateRight,
+    /// Locks the piece immediately.
+    HardLock,
+    /// Locks the piece at the next frame in real-time play, and immediately in turn-based play.
+    SoftDrop,
+    /// Puts the current piece back to the initial state.
+    Reset,
+    /// Discards the log of the previous piece.
+    Discard,
+    /// Takes back the previous piece.
+    Undo,
+    /// Saves the current position for practice.
+    SavePosition,
+}
+
+/// Key bindings and input parameters of a player.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputProfile {
+    pub name: String,
+    /// Map from key names to actions. A key name is a character such as `z`, or one of `left`,
+    /// `right`, `up`, `down`, `space`, `backspace` and `enter`.
+    pub bindings: BTreeMap<String, Action>,
+    /// The number of presses toward a wall after which a rotation also moves the piece,
+    /// i.e., the threshold of synchro moves.
+    pub das_charge: i32,
+}
+
+impl Default for InputProfile {
+    fn default() -> InputProfile {
+        let bindings = [
+            ("z", Action::Left),
+            ("c", Action::Right),
+            ("x", Action::HardLock),
+            ("m", Action::RotateRight),
+            (".", Action::RotateRight),
+            (",", Action::RotateLeft),
+            ("r", Action::Reset),
+            ("n", Action::Discard),
+            ("u", Action::Undo),
+            ("p", Action::SavePosition),
+            ("backspace", Action::Undo),
+            ("left", Action::Left),
+            ("right", Action::Right),
+            ("up", Action::HardLock),
+            ("down", Action::SoftDrop),
+        ];
+        InputProfile {
+            name: String::from("classic"),
+            bindings: bindings.iter().map(|&(key, action)| (String::from(key), action)).collect(),
+            das_charge: 6,
+        }
+    }
+}
+
+impl InputProfile {
+    pub fn action(&self, key: &str) -> Option<Action> {
+        self.bindings.get(key).cloned()
+    }
+
+    /// Checks that no reserved key is bound and the parameters are valid.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(key) = RESERVED_KEYS.iter().find(|key| self.bindings.contains_key(**key)) {
+            return Err(format!("profile {}: key `{}` is reserved", self.name, key));
+        }
+        if self.das_charge < 1 {
+            return Err(format!("profile {}: das_charge must be positive", self.name));
+        }
+        Ok(())
+    }
+}
+
+/// Profiles of players.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct InputConfig {
+    #[serde(flatten)]
+    pub profiles: BTreeMap<String, InputProfile>,
+}
+
+impl InputConfig {
+    pub fn load(filename: &str) -> InputConfig {
+        let mut file = OpenOptions::new().read(true).open(filename).unwrap();
+        let mut all = String::new();
+        file.read_to_string(&mut all).unwrap();
+        InputConfig::parse(&all).unwrap_or_else(|e| panic!("{}: {}", filename, e))
+    }
+
+    pub fn parse(s: &str) -> Result<InputConfig, String> {
+        let config: InputConfig = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        for profile in config.profiles.values() {
+            profile.validate()?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the profile of a player, the `default` profile, or the built-in profile.
+    pub fn profile_for(&self, player: Option<&str>) -> InputProfile {
+        player
+            .and_then(|player| self.profiles.get(player))
+            .or_else(|| self.profiles.get("default"))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        let config = InputConfig::parse(r#"{
+            "alice": {"name": "arrows", "bindings": {"left": "left", "space": "hard-lock"},
+                      "das_charge": 4}
+        }"#).unwrap();
+        let alice = config.profile_for(Some("alice"));
+        assert_eq!(alice.action("space"), Some(Action::HardLock));
+        assert_eq!(alice.action("z"), None);
+        assert_eq!(config.profile_for(Some("bob")), InputProfile::default());
+        assert_eq!(InputProfile::default().action(","), Some(Action::RotateLeft));
+
+        let reserved = r#"{"default": {"name": "x", "bindings": {"q": "left"}, "das_charge": 6}}"#;
+        assert!(InputConfig::parse(reserved).is_err());
+    }
+
+    #[test]
+    fn test_shipped_default_is_built_in() {
+        let shipped = include_str!("../../resources/input_profiles.json");
+        let config = InputConfig::parse(shipped).unwrap();
+        assert_eq!(config.profiles["default"], InputProfile::default());
+    }
+}
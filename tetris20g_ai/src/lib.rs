@@ -2,14 +2,30 @@
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 extern crate rand;
 
 pub mod agent;
+pub mod analysis;
+pub mod api;
+pub mod benchmark;
+pub mod bot;
 pub mod core;
+pub mod dataset;
 pub mod dataset_generator;
 pub mod enumeration;
+pub mod episode;
+pub mod fumen;
 pub mod human_manipulation;
+pub mod input_profile;
+pub mod log_check;
 pub mod logger;
+pub mod optimizer;
+pub mod pair_sampler;
 pub mod regressor;
+pub mod replay;
+pub mod self_play;
+pub mod statistics;
 pub mod utility;
+pub mod weight_map;
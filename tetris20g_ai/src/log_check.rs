@@ -0,0 +1,214 @@
+//! Module for validating annotated logs and collecting their statistics.
+//!
+//! Entries are checked one by one, so that long logs can be processed in a streaming manner.
+//! An entry is kept in the cleaned log if its decided state is reachable. Steps of the kept
+//! entries are renumbered so that they are continuous exactly where consecutive entries are
+//! consistent, which is what `dataset_generator` relies on.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use core::fix_piece;
+use enumeration::enumerate_single;
+use logger::LogInfo;
+use utility;
+
+/// A problem found in a log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The decided state cannot be reached from the field.
+    Unreachable,
+    /// The step does not follow the previous one, e.g., because of `n` in annotation.
+    Discontinuity { prev_step: i32 },
+    /// The field differs from the result of the previous placement.
+    Inconsistent,
+    /// The piece differs from the next piece of the previous entry.
+    NextPieceMismatch { expected: u8 },
+}
+
+/// A problem with the position in a log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// Index of the entry in the log file.
+    pub index: usize,
+    pub step: i32,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} (step {}): ", self.index, self.step)?;
+        match self.kind {
+            IssueKind::Unreachable => write!(f, "decided state is unreachable"),
+            IssueKind::Discontinuity { prev_step } => {
+                write!(f, "step does not follow step {}", prev_step)
+            }
+            IssueKind::Inconsistent => write!(f, "field differs from the previous placement"),
+            IssueKind::NextPieceMismatch { expected } => {
+                write!(f, "piece differs from the previous next piece {}", expected as char)
+            }
+        }
+    }
+}
+
+/// Statistics and issues of logs.
+#[derive(Debug, Clone, Default)]
+pub struct LogReport {
+    pub entries: usize,
+    /// The number of entries kept in the cleaned log.
+    pub kept: usize,
+    /// The number of continuous segments in the cleaned log.
+    pub segments: usize,
+    pub issues: Vec<Issue>,
+    /// Map from a piece type to the number of its placements.
+    pub pieces: BTreeMap<u8, usize>,
+    /// Map from the stack height before a placement to the number of entries.
+    pub heights: BTreeMap<usize, usize>,
+}
+
+impl LogReport {
+    /// Adds the statistics of another report, e.g., of another file.
+    pub fn merge(&mut self, other: &LogReport) {
+        self.entries += other.entries;
+        self.kept += other.kept;
+        self.segments += other.segments;
+        self.issues.extend(other.issues.iter().cloned());
+        for (&piece, &count) in other.pieces.iter() {
+            *self.pieces.entry(piece).or_insert(0) += count;
+        }
+        for (&height, &count) in other.heights.iter() {
+            *self.heights.entry(height).or_insert(0) += count;
+        }
+    }
+
+    /// Returns the number of issues of each kind, in the order of unreachable, discontinuity,
+    /// inconsistent and next piece mismatch.
+    pub fn issue_counts(&self) -> [usize; 4] {
+        let mut counts = [0; 4];
+        for issue in self.issues.iter() {
+            counts[match issue.kind {
+                IssueKind::Unreachable => 0,
+                IssueKind::Discontinuity { .. } => 1,
+                IssueKind::Inconsistent => 2,
+                IssueKind::NextPieceMismatch { .. } => 3,
+            }] += 1;
+        }
+        counts
+    }
+}
+
+/// Checks log entries one by one.
+pub struct LogChecker {
+    pub report: LogReport,
+    prev: Option<LogInfo>,
+    /// True if the next kept entry starts a new segment.
+    split: bool,
+    next_step: i32,
+}
+
+impl Default for LogChecker {
+    fn default() -> LogChecker {
+        LogChecker::new()
+    }
+}
+
+impl LogChecker {
+    pub fn new() -> LogChecker {
+        LogChecker {
+            report: LogReport::default(),
+            prev: None,
+            split: true,
+            next_step: 0,
+        }
+    }
+
+    /// Checks an entry, and returns it with a renumbered step if it is kept in the cleaned log.
+    pub fn check(&mut self, entry: LogInfo) -> Option<LogInfo> {
+        let index = self.report.entries;
+        self.report.entries += 1;
+        *self.report.pieces.entry(entry.decided.piece_type).or_insert(0) += 1;
+        *self.report.heights.entry(utility::stack_height(&entry.field)).or_insert(0) += 1;
+        let issue = |kind| Issue { index, step: entry.step, kind };
+
+        let reachable = enumerate_single(&entry.field, entry.decided.piece_type)
+            .iter()
+            .any(|info| info.last_state == entry.decided);
+        if !reachable {
+            self.report.issues.push(issue(IssueKind::Unreachable));
+            self.prev = None;
+            self.split = true;
+            return None;
+        }
+
+        if let Some(ref prev) = self.prev {
+            let kind = if entry.step != prev.step + 1 {
+                Some(IssueKind::Discontinuity { prev_step: prev.step })
+            } else if fix_piece(&prev.field, &prev.decided).0 != entry.field {
+                Some(IssueKind::Inconsistent)
+            } else if prev.next_piece != entry.decided.piece_type {
+                Some(IssueKind::NextPieceMismatch { expected: prev.next_piece })
+            } else {
+                None
+            };
+            if let Some(kind) = kind {
+                self.report.issues.push(issue(kind));
+                self.split = true;
+            }
+        }
+
+        if self.split {
+            if self.report.kept > 0 {
+                // leave a gap between segments
+                self.next_step += 1;
+            }
+            self.report.segments += 1;
+            self.split = false;
+        }
+        self.report.kept += 1;
+        let mut cleaned = entry.clone();
+        cleaned.step = self.next_step;
+        self.next_step += 1;
+        self.prev = Some(entry);
+        Some(cleaned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{new_piece, EMPTY_FIELD};
+
+    #[test]
+    fn test_check_log() {
+        let pieces = b"TIOSZ";
+        let mut field = EMPTY_FIELD;
+        let mut entries = vec![];
+        for (i, &piece) in pieces.iter().take(4).enumerate() {
+            let decided = enumerate_single(&field, piece)[0].last_state.clone();
+            entries.push(LogInfo {
+                field,
+                decided: decided.clone(),
+                next_piece: pieces[i + 1],
+                step: i as i32,
+                inputs: vec![],
+                spawned_at: 0,
+            });
+            field = fix_piece(&field, &decided).0;
+        }
+        // a floating piece cannot be placed
+        entries[1].decided = new_piece(b'I');
+        entries[1].decided.first = false;
+        // a skipped step
+        entries[3].step = 10;
+
+        let mut checker = LogChecker::new();
+        let steps: Vec<Option<i32>> = entries
+            .into_iter()
+            .map(|entry| checker.check(entry).map(|e| e.step))
+            .collect();
+        assert_eq!(steps, vec![Some(0), None, Some(2), Some(4)]);
+        let kinds: Vec<IssueKind> = checker.report.issues.iter().map(|i| i.kind.clone()).collect();
+        assert_eq!(kinds, vec![IssueKind::Unreachable, IssueKind::Discontinuity { prev_step: 2 }]);
+        assert_eq!(checker.report.segments, 3);
+        assert_eq!(checker.report.pieces[&b'I'], 1);
+    }
+}
@@ -1,50 +1,452 @@
 //! Module for saving and loading annotated data.
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use core::{Field, PieceState};
+//!
+//! A log file is in the JSON lines format. Since version 2, the first line is a `LogHeader`
+//! describing the session, and each of the following lines is a `LogInfo` for a placed piece.
+//! Files of version 1 have no header, and their records have no inputs and timestamps.
+//! Since version 3, a line can also be a `Retraction` removing the last records of undone
+//! pieces, which readers apply so that they only yield the remaining records.
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use core::{Command, Field, PieceState, Rules};
+use core::Position;
+use input_profile::InputProfile;
 use serde_json;
+use utility;
+
+/// The current version of the log format.
+pub const LOG_VERSION: u32 = 3;
+
+/// The maximum number of records a retraction can remove. Readers hold back this many records.
+pub const MAX_RETRACTION: usize = 100;
+
+/// Information on a play session.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    /// Seed for the piece sequence and the initially filled lines.
+    pub seed: u32,
+    /// Name of the piece randomizer, e.g., `utility::RANDOMIZER`.
+    pub randomizer: String,
+    pub rules: Rules,
+    /// The number of lines initially filled.
+    pub lines: usize,
+    pub player: Option<String>,
+    /// Date and time when the session started, in RFC 3339 format.
+    pub date: String,
+    /// Key bindings of the human player.
+    #[serde(default)]
+    pub input_profile: Option<InputProfile>,
+    /// The position a practice session started from, instead of the filled lines. The piece
+    /// sequence starts with its queue.
+    #[serde(default)]
+    pub position: Option<Position>,
+}
+
+impl SessionInfo {
+    pub fn initial_field(&self) -> Field {
+        match self.position {
+            Some(ref position) => position.field,
+            None => utility::filled_field(self.lines, Some(self.seed)),
+        }
+    }
+
+    /// Returns the first `len` pieces of the sequence.
+    pub fn pieces(&self, len: usize) -> Vec<u8> {
+        let mut seq = self.position.as_ref().map_or_else(Vec::new, |p| p.queue.clone());
+        seq.extend(utility::generate_pieces(len, Some(self.seed)));
+        seq.truncate(len);
+        seq
+    }
+}
+
+/// The first line of a log file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogHeader {
+    pub version: u32,
+    pub session: SessionInfo,
+}
+
+/// A key press and the command it was interpreted as.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputEvent {
+    /// Milliseconds from the start of the session.
+    pub time: u64,
+    /// Key name as in `InputProfile::bindings`.
+    pub key: String,
+    /// None if the key does not manipulate the piece, e.g., a reset.
+    pub command: Option<Command>,
+}
 
 /// A single log data.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LogInfo {
     pub field: Field,
     pub decided: PieceState,
     pub next_piece: u8,
     pub step: i32,
+    /// Key presses from the appearance of the piece until it was fixed.
+    #[serde(default)]
+    pub inputs: Vec<InputEvent>,
+    /// Milliseconds from the start of the session to the appearance of the piece.
+    #[serde(default)]
+    pub spawned_at: u64,
+}
+
+/// A record removing the preceding records of undone pieces.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Retraction {
+    /// The number of the last remaining records removed.
+    pub records: usize,
+}
+
+/// The number of records after which unsynced records are synced to the disk.
+const FLUSH_RECORDS: usize = 16;
+/// Seconds after which unsynced records are synced to the disk.
+const FLUSH_SECONDS: u64 = 5;
+
+/// What `Logger::open` does if the log file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingLog {
+    /// Appends records to the existing file if it was written for the same session, i.e., the
+    /// same session information except for the date. Otherwise the file is rotated.
+    Resume,
+    /// Renames the existing file to the first unused name of `<stem>.<n>.<extension>`.
+    Rotate,
 }
 
 /// A struct for saving log files.
+/// Each record is passed to the OS as soon as it is written, so that it survives the process
+/// being killed. Records are synced to the disk every few records, by `sync_if_due` every few
+/// seconds, and when the logger is dropped.
 pub struct Logger {
-    file: File,
+    writer: BufWriter<File>,
+    pending: usize,
+    last_flush: Instant,
 }
 
 impl Logger {
-    pub fn new(filename: &str) -> Logger {
-        let file = OpenOptions::new()
-            .append(true)
-            .create_new(true)
-            .open(filename)
-            .unwrap();
-        Logger { file }
+    /// Creates a new log file and writes its header. An existing file is rotated.
+    pub fn new(filename: &str, session: &SessionInfo) -> Logger {
+        Logger::open(filename, Some(session), ExistingLog::Rotate).unwrap()
+    }
+
+    /// Creates a new log file without a header, for logs whose session is unknown, e.g., ones
+    /// converted from version 1 files. An existing file is rotated.
+    pub fn without_header(filename: &str) -> Logger {
+        Logger::open(filename, None, ExistingLog::Rotate).unwrap()
+    }
+
+    /// Opens a log file. Missing parent directories are created, and an existing file is resumed
+    /// or rotated. A header is written unless a file is resumed or `session` is None.
+    pub fn open(filename: &str, session: Option<&SessionInfo>, existing: ExistingLog)
+        -> io::Result<Logger>
+    {
+        let path = Path::new(filename);
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+
+        let mut resume = false;
+        if path.exists() {
+            let same_session = match (read_header(filename)?, session) {
+                (Some(header), Some(session)) => same_session(&header.session, session),
+                (None, None) => true,
+                _ => false,
+            };
+            if existing == ExistingLog::Resume && same_session {
+                truncate_partial_line(filename)?;
+                resume = true;
+            } else {
+                fs::rename(path, rotated_name(path))?;
+            }
+        }
+
+        let file = OpenOptions::new().append(true).create(true).open(filename)?;
+        let mut logger = Logger {
+            writer: BufWriter::new(file),
+            pending: 0,
+            last_flush: Instant::now(),
+        };
+        if let (false, Some(session)) = (resume, session) {
+            let header = LogHeader {
+                version: LOG_VERSION,
+                session: session.clone(),
+            };
+            logger.write_line(&serde_json::to_string(&header).unwrap())?;
+            logger.flush()?;
+        }
+        Ok(logger)
     }
 
     pub fn save(&mut self, log_info: &LogInfo) {
         let serialized = serde_json::to_string(&log_info).unwrap();
-        self.file
-            .write(format!("{}\n", serialized).as_bytes())
-            .unwrap();
+        self.write_record(&serialized);
+    }
+
+    /// Removes the last `records` remaining records, which must be among the last
+    /// `MAX_RETRACTION` ones.
+    pub fn retract(&mut self, records: usize) {
+        let serialized = serde_json::to_string(&Retraction { records }).unwrap();
+        self.write_record(&serialized);
+    }
+
+    fn write_record(&mut self, line: &str) {
+        self.write_line(line).unwrap();
+        self.writer.flush().unwrap();
+        self.pending += 1;
+        if self.pending >= FLUSH_RECORDS {
+            self.flush().unwrap();
+        } else {
+            self.sync_if_due();
+        }
+    }
+
+    /// Syncs the records to the disk if some of them have not been synced for a few seconds.
+    /// This is meant to be called periodically, e.g., every frame or while waiting for a key.
+    pub fn sync_if_due(&mut self) {
+        if self.pending > 0 && self.last_flush.elapsed().as_secs() >= FLUSH_SECONDS {
+            self.flush().unwrap();
+        }
+    }
+
+    /// Writes the buffered records and waits until they reach the disk.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.pending = 0;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.writer.write_all(format!("{}\n", line).as_bytes())
+    }
+}
+
+impl Drop for Logger {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn same_session(x: &SessionInfo, y: &SessionInfo) -> bool {
+    SessionInfo { date: y.date.clone(), ..x.clone() } == *y
+}
+
+/// Returns the first unused name of `<stem>.<n>.<extension>` for a path.
+fn rotated_name(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    (1..)
+        .map(|n| match path.extension() {
+            Some(ext) => path.with_file_name(format!("{}.{}.{}", stem, n, ext.to_string_lossy())),
+            None => path.with_file_name(format!("{}.{}", stem, n)),
+        })
+        .find(|name| !name.exists())
+        .unwrap()
+}
+
+/// Removes a line left incomplete by a crash at the end of a file.
+fn truncate_partial_line(filename: &str) -> io::Result<()> {
+    let mut all = vec![];
+    File::open(filename)?.read_to_end(&mut all)?;
+    let len = all.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    if len < all.len() {
+        OpenOptions::new().write(true).open(filename)?.set_len(len as u64)?;
     }
+    Ok(())
+}
+
+/// Reads the header of a log file. Returns None for files of version 1.
+fn read_header(filename: &str) -> io::Result<Option<LogHeader>> {
+    let mut line = String::new();
+    BufReader::new(File::open(filename)?).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line).ok())
 }
 
 /// Loads log information.
 pub fn load_log_file(filename: &str) -> Vec<LogInfo> {
-    let mut file = OpenOptions::new().read(true).open(filename).unwrap();
-    let mut all = String::new();
-    file.read_to_string(&mut all).unwrap();
-    let mut ret = vec![];
-    for line in all.lines() {
-        let log_info = serde_json::from_str(&line).unwrap();
-        ret.push(log_info);
-    }
-    ret
+    read_log_file(filename).collect()
+}
+
+/// Reads log information one by one without loading the whole file into memory.
+pub fn read_log_file(filename: &str) -> impl Iterator<Item = LogInfo> {
+    read_log(filename).1
+}
+
+/// Reads the header of a log file, if any, and returns it with the iterator of log information.
+/// The header is None for files of version 1.
+pub fn read_log(filename: &str) -> (Option<LogHeader>, impl Iterator<Item = LogInfo>) {
+    let file = OpenOptions::new().read(true).open(filename).unwrap();
+    let mut lines = BufReader::new(file).lines().map(|line| line.unwrap()).peekable();
+    let header = lines
+        .peek()
+        .and_then(|line| serde_json::from_str::<LogHeader>(line).ok());
+    if let Some(ref header) = header {
+        assert!(
+            header.version <= LOG_VERSION,
+            "{}: unsupported log version {}",
+            filename,
+            header.version
+        );
+        lines.next();
+    }
+    let entries = Entries {
+        lines,
+        held: VecDeque::new(),
+        done: false,
+    };
+    (header, entries)
+}
+
+/// Iterator of log records, which holds back the last records until no retraction can remove
+/// them.
+struct Entries<I> {
+    lines: I,
+    held: VecDeque<LogInfo>,
+    done: bool,
+}
+
+impl<I: Iterator<Item = String>> Iterator for Entries<I> {
+    type Item = LogInfo;
+
+    fn next(&mut self) -> Option<LogInfo> {
+        while !self.done && self.held.len() <= MAX_RETRACTION {
+            let line = match self.lines.next() {
+                Some(line) => line,
+                None => {
+                    self.done = true;
+                    break;
+                }
+            };
+            match serde_json::from_str::<LogInfo>(&line) {
+                Ok(log_info) => self.held.push_back(log_info),
+                Err(e) => {
+                    let retraction: Retraction =
+                        serde_json::from_str(&line).unwrap_or_else(|_| panic!("{}", e));
+                    let len = self.held.len().saturating_sub(retraction.records);
+                    self.held.truncate(len);
+                }
+            }
+        }
+        self.held.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use core::{new_piece, EMPTY_FIELD};
+
+    #[test]
+    fn test_read_both_versions() {
+        let dir = env::temp_dir();
+        let old_file = dir.join("tetris20g_ai_test_log_v1.txt");
+        let new_file = dir.join("tetris20g_ai_test_log_v2.txt");
+        let _ = fs::remove_file(&new_file);
+
+        let log_info = LogInfo {
+            field: EMPTY_FIELD,
+            decided: new_piece(b'T'),
+            next_piece: b'I',
+            step: 0,
+            inputs: vec![InputEvent { time: 10, key: String::from("x"), command: Some(Command::Fix) }],
+            spawned_at: 3,
+        };
+        let session = SessionInfo {
+            seed: 1,
+            randomizer: String::from("test"),
+            rules: Rules::default(),
+            lines: 0,
+            player: None,
+            date: String::from("2017-11-01T00:00:00+09:00"),
+            input_profile: None,
+            position: None,
+        };
+        {
+            let mut logger = Logger::new(new_file.to_str().unwrap(), &session);
+            logger.save(&log_info);
+        }
+        let (header, logs) = read_log(new_file.to_str().unwrap());
+        assert_eq!(header.unwrap().session, session);
+        let logs: Vec<LogInfo> = logs.collect();
+        assert_eq!(logs[0].inputs, log_info.inputs);
+
+        // version 1 records lack the header, inputs and timestamps
+        let old = format!(
+            "{{\"field\":{},\"decided\":{},\"next_piece\":73,\"step\":5}}\n",
+            serde_json::to_string(&EMPTY_FIELD).unwrap(),
+            serde_json::to_string(&log_info.decided).unwrap()
+        );
+        fs::write(&old_file, old).unwrap();
+        let (header, logs) = read_log(old_file.to_str().unwrap());
+        assert!(header.is_none());
+        let logs: Vec<LogInfo> = logs.collect();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].step, 5);
+        assert!(logs[0].inputs.is_empty());
+
+        fs::remove_file(&old_file).unwrap();
+        fs::remove_file(&new_file).unwrap();
+    }
+
+    #[test]
+    fn test_resume_and_rotate() {
+        let dir = env::temp_dir().join("tetris20g_ai_test_logs");
+        let _ = fs::remove_dir_all(&dir);
+        let file = dir.join("game.txt");
+        let filename = file.to_str().unwrap();
+        let mut session = SessionInfo {
+            seed: 1,
+            randomizer: String::from("test"),
+            rules: Rules::default(),
+            lines: 0,
+            player: None,
+            date: String::from("2017-11-01T00:00:00+09:00"),
+            input_profile: None,
+            position: None,
+        };
+        let log_info = |step| LogInfo {
+            field: EMPTY_FIELD,
+            decided: new_piece(b'T'),
+            next_piece: b'I',
+            step,
+            inputs: vec![],
+            spawned_at: 0,
+        };
+
+        // the parent directory is created
+        Logger::new(filename, &session).save(&log_info(0));
+        // a line left by a crash
+        OpenOptions::new().append(true).open(&file).unwrap().write_all(b"{\"fie").unwrap();
+
+        session.date = String::from("2017-11-02T00:00:00+09:00");
+        Logger::open(filename, Some(&session), ExistingLog::Resume).unwrap().save(&log_info(1));
+        let steps: Vec<i32> = read_log_file(filename).map(|log| log.step).collect();
+        assert_eq!(steps, vec![0, 1]);
+        // a retraction removes the last records
+        let mut logger = Logger::open(filename, Some(&session), ExistingLog::Resume).unwrap();
+        logger.save(&log_info(2));
+        // a record is passed to the OS before it is synced
+        assert_eq!(read_log_file(filename).count(), 3);
+        logger.save(&log_info(3));
+        logger.retract(2);
+        drop(logger);
+        let steps: Vec<i32> = read_log_file(filename).map(|log| log.step).collect();
+        assert_eq!(steps, vec![0, 1]);
+
+        // a different session is not resumed
+        session.seed = 2;
+        Logger::open(filename, Some(&session), ExistingLog::Resume).unwrap().save(&log_info(5));
+        assert_eq!(read_log(filename).0.unwrap().session.seed, 2);
+        let rotated = dir.join("game.1.txt");
+        assert_eq!(read_log_file(rotated.to_str().unwrap()).count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
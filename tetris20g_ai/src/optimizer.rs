@@ -0,0 +1,240 @@
+//! Module for derivative-free optimization of weights by the cross-entropy method.
+//!
+//! Each generation samples a population from a diagonal Gaussian distribution, evaluates them
+//! and refits the distribution to the elite samples. The whole state is serializable so that
+//! an optimization can be checkpointed after every generation and resumed later.
+use std::f64;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use rand::distributions::normal::StandardNormal;
+use serde_json;
+
+use episode::run_parallel;
+
+/// Hyper parameters of the cross-entropy method.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CemConfig {
+    /// The number of samples in a generation.
+    pub population: usize,
+    /// The ratio of samples used for refitting the distribution.
+    pub elite_ratio: f64,
+    /// Standard deviation added to the refitted distribution to avoid early convergence.
+    pub noise: f32,
+    /// The number of episodes for evaluating a sample.
+    pub episodes: usize,
+    /// Seed for sampling and for the episodes.
+    pub seed: u32,
+    /// Episodes are truncated after this number of pieces.
+    pub max_steps: usize,
+    /// The number of lines initially filled at random in the episodes.
+    pub lines: usize,
+}
+
+/// Summary of a finished generation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenerationSummary {
+    pub generation: usize,
+    pub best_fitness: f64,
+    pub mean_fitness: f64,
+    pub elite_fitness: f64,
+}
+
+/// State of the optimization which is saved as a checkpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CemState {
+    pub generation: usize,
+    pub mean: Vec<f32>,
+    pub stdev: Vec<f32>,
+    pub best_params: Vec<f32>,
+    pub best_fitness: f64,
+    pub history: Vec<GenerationSummary>,
+    /// The configuration the optimization runs with. None in checkpoints saved before it was
+    /// recorded.
+    #[serde(default)]
+    pub config: Option<CemConfig>,
+}
+
+impl CemState {
+    pub fn new(mean: Vec<f32>, stdev: f32, config: &CemConfig) -> CemState {
+        let stdev = vec![stdev; mean.len()];
+        CemState {
+            generation: 0,
+            best_params: mean.clone(),
+            mean,
+            stdev,
+            best_fitness: f64::MIN,
+            history: vec![],
+            config: Some(config.clone()),
+        }
+    }
+
+    pub fn load(filename: &str) -> CemState {
+        let mut file = OpenOptions::new().read(true).open(filename).unwrap();
+        let mut all = String::new();
+        file.read_to_string(&mut all).unwrap();
+        serde_json::from_str(&all).unwrap()
+    }
+
+    /// Saves the state. The file is replaced atomically, so an interrupted save never
+    /// corrupts the previous checkpoint.
+    pub fn save(&self, filename: &str) {
+        let tmp = format!("{}.tmp", filename);
+        {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp)
+                .unwrap();
+            file.write_all(serde_json::to_string(&self).unwrap().as_bytes()).unwrap();
+            file.sync_all().unwrap();
+        }
+        fs::rename(&tmp, filename).unwrap();
+    }
+
+    /// Checks that an optimization is resumed with the configuration it was started with, so
+    /// that generations of different configurations are not mixed. A state without the
+    /// configuration takes the given one.
+    pub fn resume_with(&mut self, config: &CemConfig) -> Result<(), String> {
+        match self.config {
+            Some(ref saved) if saved != config => Err(format!(
+                "the configuration differs from the checkpoint: {:?}",
+                saved
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.config = Some(config.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Samples the population of the current generation. The result depends only on the
+    /// seed and the generation, so a resumed run samples the same population.
+    pub fn sample(&self, config: &CemConfig) -> Vec<Vec<f32>> {
+        let mut rng: XorShiftRng =
+            SeedableRng::from_seed([config.seed, self.generation as u32, 0x2545_f491, 1]);
+        (0..config.population)
+            .map(|_| {
+                self.mean
+                    .iter()
+                    .zip(self.stdev.iter())
+                    .map(|(&m, &s)| {
+                        let StandardNormal(z) = rng.gen::<StandardNormal>();
+                        m + s * z as f32
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Refits the distribution with evaluated samples and advances the generation.
+    pub fn update(&mut self, config: &CemConfig, samples: Vec<Vec<f32>>, fitness: &[f64])
+        -> GenerationSummary
+    {
+        let mut order: Vec<usize> = (0..samples.len()).collect();
+        order.sort_by(|&a, &b| fitness[b].partial_cmp(&fitness[a]).unwrap());
+        let n_elite = ((samples.len() as f64 * config.elite_ratio).ceil() as usize)
+            .max(1)
+            .min(samples.len());
+        let elite = &order[..n_elite];
+
+        for (k, (mean, stdev)) in self.mean.iter_mut().zip(self.stdev.iter_mut()).enumerate() {
+            let m = elite.iter().map(|&i| samples[i][k]).sum::<f32>() / n_elite as f32;
+            let var = elite.iter().map(|&i| (samples[i][k] - m).powi(2)).sum::<f32>() /
+                n_elite as f32;
+            *mean = m;
+            *stdev = var.sqrt() + config.noise;
+        }
+
+        let best = order[0];
+        if fitness[best] > self.best_fitness {
+            self.best_fitness = fitness[best];
+            self.best_params = samples[best].clone();
+        }
+
+        let summary = GenerationSummary {
+            generation: self.generation,
+            best_fitness: fitness[best],
+            mean_fitness: fitness.iter().sum::<f64>() / fitness.len() as f64,
+            elite_fitness: elite.iter().map(|&i| fitness[i]).sum::<f64>() / n_elite as f64,
+        };
+        self.history.push(summary.clone());
+        self.generation += 1;
+        summary
+    }
+}
+
+/// Returns the seed of `episode`-th evaluation episode in a generation.
+/// All samples in the same generation are evaluated on the same episodes.
+pub fn episode_seed(config: &CemConfig, generation: usize, episode: usize) -> u32 {
+    config
+        .seed
+        .wrapping_mul(1_000_003)
+        .wrapping_add((generation * config.episodes + episode) as u32 + 1)
+}
+
+/// Runs one generation. `evaluate` receives parameters and an episode seed, and returns
+/// the fitness of the episode. Fitness of a sample is the average over `config.episodes`
+/// episodes, which are run in parallel by `threads` threads.
+pub fn run_generation<F>(state: &mut CemState, config: &CemConfig, threads: usize, evaluate: F)
+    -> GenerationSummary
+where
+    F: Fn(&[f32], u32) -> f64 + Sync,
+{
+    let samples = state.sample(config);
+    let mut tasks = vec![];
+    for i in 0..samples.len() {
+        for episode in 0..config.episodes {
+            tasks.push((i, episode_seed(config, state.generation, episode)));
+        }
+    }
+    let results = run_parallel(&tasks, threads, |&(i, seed)| evaluate(&samples[i], seed));
+
+    let mut fitness = vec![0.0; samples.len()];
+    for (&(i, _), result) in tasks.iter().zip(results) {
+        fitness[i] += result / config.episodes as f64;
+    }
+    state.update(config, samples, &fitness)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cem_maximizes_quadratic() {
+        let config = CemConfig {
+            population: 50,
+            elite_ratio: 0.2,
+            noise: 0.01,
+            episodes: 1,
+            seed: 1,
+            max_steps: 1,
+            lines: 0,
+        };
+        let target = [1.0f32, -2.0, 0.5];
+        let mut state = CemState::new(vec![0.0; 3], 2.0, &config);
+        for _ in 0..40 {
+            run_generation(&mut state, &config, 2, |params, _| {
+                -params
+                    .iter()
+                    .zip(target.iter())
+                    .map(|(p, t)| ((p - t) as f64).powi(2))
+                    .sum::<f64>()
+            });
+        }
+        for (p, t) in state.mean.iter().zip(target.iter()) {
+            assert!((p - t).abs() < 0.1);
+        }
+        assert_eq!(state.generation, 40);
+
+        assert!(state.resume_with(&config).is_ok());
+        assert!(state.resume_with(&CemConfig { population: 10, ..config.clone() }).is_err());
+        assert!(state.resume_with(&CemConfig { max_steps: 2, ..config.clone() }).is_err());
+        state.config = None;
+        assert!(state.resume_with(&CemConfig { episodes: 2, ..config }).is_ok());
+        assert_eq!(state.config.unwrap().episodes, 2);
+    }
+}
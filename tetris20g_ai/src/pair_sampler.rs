@@ -0,0 +1,252 @@
+//! Module for strategies choosing which alternatives are paired with the human choice
+//! when a dataset is generated.
+use std::collections::BTreeMap;
+use rand::{Rng, XorShiftRng};
+
+use core::Field;
+use utility;
+
+/// A two-step resulting field with its value score. The score is available only when
+/// a model is given to the dataset generator.
+pub struct Scored {
+    pub field: Field,
+    pub value: Option<f32>,
+}
+
+/// Strategy for selecting alternatives at a step.
+pub trait PairSampler {
+    /// Name of the strategy with its parameters, which is recorded in the manifest.
+    fn name(&self) -> String;
+
+    /// Returns true if the strategy requires value scores.
+    fn needs_model(&self) -> bool {
+        false
+    }
+
+    /// Returns the indices of `candidates` paired with `best`.
+    fn sample(&self, best: &Scored, candidates: &[Scored], rng: &mut XorShiftRng) -> Vec<usize>;
+}
+
+/// Keeps each alternative independently with a given probability.
+pub struct Uniform {
+    pub keep_rate: f64,
+}
+
+impl PairSampler for Uniform {
+    fn name(&self) -> String {
+        format!("uniform:{}", self.keep_rate)
+    }
+
+    fn sample(&self, _best: &Scored, candidates: &[Scored], rng: &mut XorShiftRng) -> Vec<usize> {
+        (0..candidates.len())
+            .filter(|_| rng.gen::<f64>() < self.keep_rate)
+            .collect()
+    }
+}
+
+fn order_by_value(candidates: &[Scored]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by(|&a, &b| {
+        candidates[b].value.unwrap().partial_cmp(&candidates[a].value.unwrap()).unwrap()
+    });
+    order
+}
+
+/// Keeps the top `ratio` of alternatives in terms of value scores.
+pub struct TopRatio {
+    pub ratio: f64,
+}
+
+impl PairSampler for TopRatio {
+    fn name(&self) -> String {
+        format!("top-ratio:{}", self.ratio)
+    }
+
+    fn needs_model(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, _best: &Scored, candidates: &[Scored], _rng: &mut XorShiftRng) -> Vec<usize> {
+        let mut order = order_by_value(candidates);
+        order.truncate((self.ratio * candidates.len() as f64) as usize);
+        order
+    }
+}
+
+/// Keeps the `k` alternatives which the model values most, i.e., hard negatives.
+pub struct TopK {
+    pub k: usize,
+}
+
+impl PairSampler for TopK {
+    fn name(&self) -> String {
+        format!("top-k:{}", self.k)
+    }
+
+    fn needs_model(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, _best: &Scored, candidates: &[Scored], _rng: &mut XorShiftRng) -> Vec<usize> {
+        let mut order = order_by_value(candidates);
+        order.truncate(self.k);
+        order
+    }
+}
+
+/// Keeps alternatives whose values are larger than the value of the human choice minus
+/// `margin`, which are the pairs the model is wrong or unsure about.
+pub struct ValueMargin {
+    pub margin: f32,
+}
+
+impl PairSampler for ValueMargin {
+    fn name(&self) -> String {
+        format!("margin:{}", self.margin)
+    }
+
+    fn needs_model(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, best: &Scored, candidates: &[Scored], _rng: &mut XorShiftRng) -> Vec<usize> {
+        let threshold = best.value.unwrap() - self.margin;
+        (0..candidates.len())
+            .filter(|&i| candidates[i].value.unwrap() >= threshold)
+            .collect()
+    }
+}
+
+/// Groups alternatives by the stack height of resulting fields, and keeps at most
+/// `per_height` alternatives chosen at random from each group.
+pub struct StratifiedByHeight {
+    pub per_height: usize,
+}
+
+impl PairSampler for StratifiedByHeight {
+    fn name(&self) -> String {
+        format!("stratified:{}", self.per_height)
+    }
+
+    fn sample(&self, _best: &Scored, candidates: &[Scored], rng: &mut XorShiftRng) -> Vec<usize> {
+        let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (i, candidate) in candidates.iter().enumerate() {
+            groups.entry(utility::stack_height(&candidate.field)).or_default().push(i);
+        }
+        let mut res = vec![];
+        for (_, mut group) in groups {
+            rng.shuffle(&mut group);
+            group.truncate(self.per_height);
+            res.extend(group);
+        }
+        res.sort();
+        res
+    }
+}
+
+/// Parses a strategy written as `<name>:<parameter>`. Available strategies are
+/// `uniform:<keep rate>`, `top-ratio:<ratio>`, `top-k:<k>`, `margin:<margin>` and
+/// `stratified:<alternatives per height>`.
+pub fn parse_sampler(s: &str) -> Result<Box<dyn PairSampler>, String> {
+    let mut parts = s.splitn(2, ':');
+    let name = parts.next().unwrap();
+    let param = parts.next().ok_or(format!("parameter is missing in `{}`", s))?;
+    let float = || param.parse::<f64>().map_err(|e| format!("{}: {}", s, e));
+    let int = || param.parse::<usize>().map_err(|e| format!("{}: {}", s, e));
+    Ok(match name {
+        "uniform" => Box::new(Uniform { keep_rate: float()? }),
+        "top-ratio" => Box::new(TopRatio { ratio: float()? }),
+        "top-k" => Box::new(TopK { k: int()? }),
+        "margin" => Box::new(ValueMargin { margin: float()? as f32 }),
+        "stratified" => Box::new(StratifiedByHeight { per_height: int()? }),
+        _ => return Err(format!("unknown sampler: {}", name)),
+    })
+}
+
+/// The numbers of steps, candidates and pairs a log file contributed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SourceContribution {
+    pub source: String,
+    pub steps: usize,
+    pub candidates: usize,
+    pub pairs: usize,
+}
+
+/// Summary of a dataset generation run with a strategy. Contributions are aggregated per log
+/// file, so the summary does not grow with the number of steps.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SamplingSummary {
+    pub strategy: String,
+    pub seed: u32,
+    pub sources: Vec<SourceContribution>,
+    /// A map from the number of pairs to the number of steps which contributed it.
+    pub histogram: BTreeMap<usize, usize>,
+}
+
+impl SamplingSummary {
+    pub fn new(strategy: String, seed: u32) -> SamplingSummary {
+        SamplingSummary {
+            strategy,
+            seed,
+            sources: vec![],
+            histogram: BTreeMap::new(),
+        }
+    }
+
+    /// Counts a step of a log file which had `candidates` alternatives and contributed `pairs`.
+    pub fn add_step(&mut self, source: &str, candidates: usize, pairs: usize) {
+        if self.sources.last().is_none_or(|s| s.source != source) {
+            self.sources.push(SourceContribution {
+                source: String::from(source),
+                steps: 0,
+                candidates: 0,
+                pairs: 0,
+            });
+        }
+        let contribution = self.sources.last_mut().unwrap();
+        contribution.steps += 1;
+        contribution.candidates += candidates;
+        contribution.pairs += pairs;
+        *self.histogram.entry(pairs).or_insert(0) += 1;
+    }
+
+    pub fn steps(&self) -> usize {
+        self.sources.iter().map(|s| s.steps).sum()
+    }
+
+    pub fn pairs(&self) -> usize {
+        self.sources.iter().map(|s| s.pairs).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::EMPTY_FIELD;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_model_based_samplers() {
+        let scored = |value| Scored { field: EMPTY_FIELD, value: Some(value) };
+        let best = scored(1.0);
+        let candidates = vec![scored(0.0), scored(3.0), scored(0.8), scored(-2.0)];
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        assert_eq!(TopK { k: 2 }.sample(&best, &candidates, &mut rng), vec![1, 2]);
+        assert_eq!(ValueMargin { margin: 0.5 }.sample(&best, &candidates, &mut rng), vec![1, 2]);
+        assert_eq!(TopRatio { ratio: 0.75 }.sample(&best, &candidates, &mut rng), vec![1, 2, 0]);
+        assert!(parse_sampler("uniform:0.5").is_ok());
+        assert!(parse_sampler("top-k").is_err());
+    }
+
+    #[test]
+    fn test_summary_aggregates_sources() {
+        let mut summary = SamplingSummary::new(String::from("top-k:2"), 1);
+        summary.add_step("a.txt", 10, 2);
+        summary.add_step("a.txt", 5, 2);
+        summary.add_step("b.txt", 1, 1);
+        assert_eq!(summary.sources.len(), 2);
+        assert_eq!(summary.sources[0].candidates, 15);
+        assert_eq!((summary.steps(), summary.pairs()), (3, 5));
+        assert_eq!(summary.histogram.get(&2), Some(&2));
+    }
+}
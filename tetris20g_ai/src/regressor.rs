@@ -1,34 +1,231 @@
 //! Module for defining learning models.
 
+use std::collections::HashSet;
 use std::fs::OpenOptions;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::sync::Arc;
+use rand::{Rng, SeedableRng, XorShiftRng};
 use core::{HEIGHT, WIDTH, Field, EMPTY_FIELD};
 
+/// Identifier of `extract_feature` stored in dataset files.
+/// This must be changed whenever the layout of the feature vector changes.
+pub const FEATURE_EXTRACTOR_ID: u32 = 1;
+
 /// Extracts feature vector for a given field.
 pub fn extract_feature(field: &Field) -> Vec<bool> {
     let mut res = vec![];
-    for ai in 0..HEIGHT {
-        for aj in 0..WIDTH {
-            for di in 0..4 {
-                for dj in -3i8..4 {
-                    let bi = ai + di;
-                    let bj = (aj as i8) + dj;
-                    if bi >= HEIGHT || bj < 0 || bj >= WIDTH as i8 {
-                        continue;
-                    }
-                    let bj = bj as usize;
-                    if ai == bi && aj == bj {
+    for_each_pair(|ai, aj, di, dj| {
+        let (bi, bj) = (ai + di, (aj as i8 + dj) as usize);
+        res.push(field[ai][aj] == b'.' && field[bi][bj] != b'.');
+        res.push(field[ai][aj] != b'.' && field[bi][bj] == b'.');
+    });
+    res
+}
+
+/// Calls `f(i, j, di, dj)` for each pair of cells `(i, j)` and `(i + di, j + dj)` in the field,
+/// in the order of features. Each pair has a feature of each polarity.
+fn for_each_pair<F: FnMut(usize, usize, usize, i8)>(mut f: F) {
+    for i in 0..HEIGHT {
+        for j in 0..WIDTH {
+            for di in 0..MAX_DI {
+                for dj in -MAX_DJ..MAX_DJ + 1 {
+                    let bj = j as i8 + dj;
+                    if i + di >= HEIGHT || bj < 0 || bj >= WIDTH as i8 || (di == 0 && dj == 0) {
                         continue;
                     }
-                    res.push(field[ai][aj] == b'.' && field[bi][bj] != b'.');
-                    res.push(field[ai][aj] != b'.' && field[bi][bj] == b'.');
+                    f(i, j, di, dj);
                 }
             }
         }
     }
+}
+
+/// The maximum row offset of a feature plus one. Row offsets are `0..MAX_DI`.
+pub const MAX_DI: usize = 4;
+/// The maximum absolute column offset of a feature. Column offsets are `-MAX_DJ..=MAX_DJ`.
+pub const MAX_DJ: i8 = 3;
+
+/// Which of the two cells of a feature is empty. The first cell is never below the second one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Polarity {
+    /// The first cell is empty and the second one is filled.
+    EmptyAboveFilled,
+    /// The first cell is filled and the second one is empty.
+    FilledAboveEmpty,
+}
+
+/// A feature of `extract_feature`, defined by cells `(i, j)` and `(i + di, j + dj)`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Feature {
+    pub i: usize,
+    pub j: usize,
+    pub di: usize,
+    pub dj: i8,
+    pub polarity: Polarity,
+}
+
+impl Feature {
+    /// The second cell.
+    pub fn other(&self) -> (usize, usize) {
+        (self.i + self.di, (self.j as i8 + self.dj) as usize)
+    }
+
+    pub fn is_active(&self, field: &Field) -> bool {
+        let (bi, bj) = self.other();
+        let (a, b) = (field[self.i][self.j] == b'.', field[bi][bj] == b'.');
+        match self.polarity {
+            Polarity::EmptyAboveFilled => a && !b,
+            Polarity::FilledAboveEmpty => !a && b,
+        }
+    }
+}
+
+/// Returns the features in the order of `extract_feature`, which is also the layout of the
+/// weights of `LinearRegressor`.
+pub fn feature_layout() -> Vec<Feature> {
+    let mut res = vec![];
+    for_each_pair(|i, j, di, dj| {
+        // in the same order as the two pushes of `extract_feature`
+        let polarities = [Polarity::EmptyAboveFilled, Polarity::FilledAboveEmpty];
+        for &polarity in polarities.iter() {
+            res.push(Feature { i, j, di, dj, polarity });
+        }
+    });
     res
 }
 
+/// Contributions of features to the value of a field, or to the difference between the values
+/// of two fields.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Explanation {
+    /// The sum of the contributions.
+    pub value: f32,
+    /// Active features and their weights. In a difference, the weights of the features active
+    /// only in the other field are negated.
+    pub features: Vec<(Feature, f32)>,
+    /// Sums by offset, where `by_offset[di][dj + MAX_DJ]` is for `(di, dj)`.
+    pub by_offset: Vec<Vec<f32>>,
+    /// Sums by cell. A feature contributes a half of its weight to each of its two cells.
+    pub by_cell: Vec<Vec<f32>>,
+}
+
+impl Explanation {
+    fn new(features: Vec<(Feature, f32)>) -> Explanation {
+        let mut by_offset = vec![vec![0.0; 2 * MAX_DJ as usize + 1]; MAX_DI];
+        let mut by_cell = vec![vec![0.0; WIDTH]; HEIGHT];
+        for &(feature, weight) in features.iter() {
+            by_offset[feature.di][(feature.dj + MAX_DJ) as usize] += weight;
+            let (bi, bj) = feature.other();
+            by_cell[feature.i][feature.j] += weight / 2.0;
+            by_cell[bi][bj] += weight / 2.0;
+        }
+        let value = features.iter().map(|&(_, weight)| weight).sum();
+        Explanation { value, features, by_offset, by_cell }
+    }
+
+    /// Returns the explanation of the difference from another one of the same model, from which
+    /// the features active in both are removed.
+    pub fn diff(&self, other: &Explanation) -> Explanation {
+        let these: HashSet<Feature> = self.features.iter().map(|&(f, _)| f).collect();
+        let those: HashSet<Feature> = other.features.iter().map(|&(f, _)| f).collect();
+        let mut features: Vec<(Feature, f32)> = self
+            .features
+            .iter()
+            .filter(|(f, _)| !those.contains(f))
+            .cloned()
+            .chain(other.features.iter().filter(|(f, _)| !these.contains(f)).map(|&(f, w)| (f, -w)))
+            .collect();
+        features.sort_by_key(|&(f, _)| f);
+        Explanation::new(features)
+    }
+
+    /// The sum over a region of the field, e.g., `region(15..20, 0..WIDTH)` for the bottom five
+    /// rows.
+    pub fn region(&self, rows: Range<usize>, columns: Range<usize>) -> f32 {
+        self.by_cell[rows].iter().map(|row| row[columns.clone()].iter().sum::<f32>()).sum()
+    }
+}
+
+/// Common interface of models which evaluate how good a field is.
+pub trait ValueModel: Send + Sync {
+    /// Returns the value score of a given field. Larger is better.
+    fn predict(&self, field: &Field) -> f32;
+
+    /// Serializes the model into the text format accepted by `load_model_direct`.
+    fn dump(&self) -> String;
+
+    /// Explains the value of a field by features, if the model is linear in them.
+    fn explain(&self, _field: &Field) -> Option<Explanation> {
+        None
+    }
+}
+
+/// A shared model, so that agents of parallel episodes can use a model loaded once.
+impl<M: ValueModel + ?Sized> ValueModel for Arc<M> {
+    fn predict(&self, field: &Field) -> f32 {
+        (**self).predict(field)
+    }
+
+    fn dump(&self) -> String {
+        (**self).dump()
+    }
+
+    fn explain(&self, field: &Field) -> Option<Explanation> {
+        (**self).explain(field)
+    }
+}
+
+/// Models which can be trained online by stochastic gradient descent.
+pub trait TrainableModel: ValueModel {
+    /// Moves the prediction for `field` toward `target` by one gradient step of squared error.
+    fn sgd_update(&mut self, field: &Field, target: f32, learning_rate: f32);
+}
+
+/// Loads a model file, detecting its type from the content.
+pub fn load_model(filename: &str) -> Box<dyn ValueModel> {
+    load_trainable_model(filename)
+}
+
+/// Loads a model from a string. A string starting with `mlp` is treated as `MlpRegressor`,
+/// otherwise it is treated as a space-separated weights of `LinearRegressor`.
+pub fn load_model_direct(param_string: &str) -> Box<dyn ValueModel> {
+    load_trainable_model_direct(param_string)
+}
+
+/// Loads a trainable model in the same manner as `load_model`.
+pub fn load_trainable_model(filename: &str) -> Box<dyn TrainableModel> {
+    let mut file = OpenOptions::new().read(true).open(filename).unwrap();
+    let mut all = String::new();
+    file.read_to_string(&mut all).unwrap();
+    load_trainable_model_direct(&all)
+}
+
+/// Loads a trainable model from a string in the same manner as `load_model_direct`. Every model
+/// format is trainable, so this is the only place where formats are told apart.
+pub fn load_trainable_model_direct(param_string: &str) -> Box<dyn TrainableModel> {
+    if param_string.trim_start().starts_with(MLP_MAGIC) {
+        let mut model = MlpRegressor::new(0, 0);
+        model.load_direct(param_string);
+        Box::new(model)
+    } else {
+        let mut model = LinearRegressor::new();
+        model.load_direct(param_string);
+        Box::new(model)
+    }
+}
+
+/// Saves a model into a file.
+pub fn save_model(model: &dyn ValueModel, filename: &str) {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(filename)
+        .unwrap();
+    file.write_all(model.dump().as_bytes()).unwrap();
+}
+
 /// Linear regression model.
 pub struct LinearRegressor {
     params: Vec<f32>,
@@ -40,6 +237,14 @@ impl LinearRegressor {
         LinearRegressor { params: vec![0.0; dim.len()] }
     }
 
+    pub fn from_params(params: Vec<f32>) -> LinearRegressor {
+        LinearRegressor { params }
+    }
+
+    pub fn params(&self) -> &[f32] {
+        &self.params
+    }
+
     pub fn load(&mut self, filename: &str) {
         //! Load space-separated weight file.
         let mut file = OpenOptions::new().read(true).open(filename).unwrap();
@@ -65,4 +270,236 @@ impl LinearRegressor {
             },
         )
     }
+
+    /// Returns the active features of a field with their weights.
+    pub fn explain(&self, field: &Field) -> Explanation {
+        let feature = extract_feature(field);
+        let features = feature_layout()
+            .into_iter()
+            .zip(self.params.iter().zip(feature.iter()))
+            .filter(|&(_, (_, &active))| active)
+            .map(|(f, (&weight, _))| (f, weight))
+            .collect();
+        Explanation::new(features)
+    }
+
+    /// Explains why `field` is valued more or less than `base`.
+    pub fn explain_diff(&self, field: &Field, base: &Field) -> Explanation {
+        self.explain(field).diff(&self.explain(base))
+    }
+}
+
+impl ValueModel for LinearRegressor {
+    fn predict(&self, field: &Field) -> f32 {
+        LinearRegressor::predict(self, field)
+    }
+
+    fn dump(&self) -> String {
+        let params: Vec<String> = self.params.iter().map(|x| format!("{:.14}", x)).collect();
+        params.join(" ")
+    }
+
+    fn explain(&self, field: &Field) -> Option<Explanation> {
+        Some(LinearRegressor::explain(self, field))
+    }
+}
+
+/// A linear model with weights of both signs and various sizes, for tests.
+#[cfg(test)]
+pub fn test_regressor() -> LinearRegressor {
+    let dim = extract_feature(&EMPTY_FIELD).len();
+    LinearRegressor::from_params((0..dim).map(|i| ((i * 7919) % 13) as f32 - 6.0).collect())
+}
+
+impl TrainableModel for LinearRegressor {
+    /// The step is normalized by the number of active features, so that `learning_rate`
+    /// is the fraction of the error removed by a single update.
+    fn sgd_update(&mut self, field: &Field, target: f32, learning_rate: f32) {
+        let feature = extract_feature(field);
+        let active: Vec<usize> = (0..feature.len()).filter(|&i| feature[i]).collect();
+        if active.is_empty() {
+            return;
+        }
+        let error = target - LinearRegressor::predict(self, field);
+        let step = learning_rate * error / active.len() as f32;
+        for i in active {
+            self.params[i] += step;
+        }
+    }
+}
+
+const MLP_MAGIC: &str = "mlp";
+
+/// Multilayer perceptron model with one hidden layer and ReLU activation.
+///
+/// The text format is `mlp <input dim> <hidden dim>` followed by the first layer weights in
+/// (hidden, input) row-major order, the first layer biases, the second layer weights and the
+/// second layer bias, all separated by whitespaces.
+pub struct MlpRegressor {
+    input_dim: usize,
+    hidden_dim: usize,
+    w1: Vec<f32>, // (input, hidden) layout so that active features can be summed up directly
+    b1: Vec<f32>,
+    w2: Vec<f32>,
+    b2: f32,
+}
+
+impl MlpRegressor {
+    /// Creates a model with small random weights.
+    pub fn new(hidden_dim: usize, seed: u32) -> MlpRegressor {
+        let input_dim = extract_feature(&EMPTY_FIELD).len();
+        let mut rng: XorShiftRng = SeedableRng::from_seed([seed, seed ^ 0x9e37_79b9, 1, 2]);
+        let scale1 = 1.0 / (input_dim as f32).sqrt();
+        let scale2 = 1.0 / (hidden_dim.max(1) as f32).sqrt();
+        let w1 = (0..input_dim * hidden_dim)
+            .map(|_| rng.gen_range(-scale1, scale1))
+            .collect();
+        let w2 = (0..hidden_dim).map(|_| rng.gen_range(-scale2, scale2)).collect();
+        MlpRegressor {
+            input_dim,
+            hidden_dim,
+            w1,
+            b1: vec![0.0; hidden_dim],
+            w2,
+            b2: 0.0,
+        }
+    }
+
+    pub fn load(&mut self, filename: &str) {
+        let mut file = OpenOptions::new().read(true).open(filename).unwrap();
+        let mut all = String::new();
+        file.read_to_string(&mut all).unwrap();
+        self.load_direct(&all);
+    }
+
+    pub fn load_direct(&mut self, param_string: &str) {
+        let mut tokens = param_string.split_whitespace();
+        assert_eq!(tokens.next(), Some(MLP_MAGIC));
+        let input_dim: usize = tokens.next().unwrap().parse().unwrap();
+        let hidden_dim: usize = tokens.next().unwrap().parse().unwrap();
+        let values: Vec<f32> = tokens.map(|x| x.parse::<f32>().unwrap()).collect();
+        assert_eq!(values.len(), input_dim * hidden_dim + 2 * hidden_dim + 1);
+
+        let mut w1 = vec![0.0; input_dim * hidden_dim];
+        for h in 0..hidden_dim {
+            for i in 0..input_dim {
+                w1[i * hidden_dim + h] = values[h * input_dim + i];
+            }
+        }
+        let rest = &values[input_dim * hidden_dim..];
+        self.input_dim = input_dim;
+        self.hidden_dim = hidden_dim;
+        self.w1 = w1;
+        self.b1 = rest[..hidden_dim].to_vec();
+        self.w2 = rest[hidden_dim..2 * hidden_dim].to_vec();
+        self.b2 = rest[2 * hidden_dim];
+    }
+
+    /// Returns the activations of the hidden layer for a given feature vector.
+    fn hidden(&self, feature: &[bool]) -> Vec<f32> {
+        let mut hidden = self.b1.clone();
+        for (i, _) in feature.iter().enumerate().filter(|&(_, &b)| b) {
+            let row = &self.w1[i * self.hidden_dim..(i + 1) * self.hidden_dim];
+            for (h, w) in hidden.iter_mut().zip(row) {
+                *h += w;
+            }
+        }
+        for h in hidden.iter_mut() {
+            *h = h.max(0.0);
+        }
+        hidden
+    }
+
+    pub fn predict(&self, field: &Field) -> f32 {
+        let feature = extract_feature(field);
+        let hidden = self.hidden(&feature);
+        hidden.iter().zip(self.w2.iter()).fold(self.b2, |sum, (&h, &w)| sum + h * w)
+    }
+}
+
+impl ValueModel for MlpRegressor {
+    fn predict(&self, field: &Field) -> f32 {
+        MlpRegressor::predict(self, field)
+    }
+
+    fn dump(&self) -> String {
+        let mut res = format!("{} {} {}", MLP_MAGIC, self.input_dim, self.hidden_dim);
+        for h in 0..self.hidden_dim {
+            for i in 0..self.input_dim {
+                res += &format!(" {:.14}", self.w1[i * self.hidden_dim + h]);
+            }
+        }
+        for x in self.b1.iter().chain(self.w2.iter()) {
+            res += &format!(" {:.14}", x);
+        }
+        res += &format!(" {:.14}", self.b2);
+        res
+    }
+}
+
+impl TrainableModel for MlpRegressor {
+    fn sgd_update(&mut self, field: &Field, target: f32, learning_rate: f32) {
+        let feature = extract_feature(field);
+        let hidden = self.hidden(&feature);
+        let prediction = hidden.iter().zip(self.w2.iter()).fold(self.b2, |sum, (&h, &w)| sum + h * w);
+        let error = target - prediction;
+
+        // back propagation of squared error (the factor 2 is absorbed in the learning rate)
+        let grad_hidden: Vec<f32> = hidden
+            .iter()
+            .zip(self.w2.iter())
+            .map(|(&h, &w)| if h > 0.0 { error * w } else { 0.0 })
+            .collect();
+        for (w, &h) in self.w2.iter_mut().zip(hidden.iter()) {
+            *w += learning_rate * error * h;
+        }
+        self.b2 += learning_rate * error;
+        for (b, &g) in self.b1.iter_mut().zip(grad_hidden.iter()) {
+            *b += learning_rate * g;
+        }
+        for (i, _) in feature.iter().enumerate().filter(|&(_, &b)| b) {
+            let row = &mut self.w1[i * self.hidden_dim..(i + 1) * self.hidden_dim];
+            for (w, &g) in row.iter_mut().zip(grad_hidden.iter()) {
+                *w += learning_rate * g;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utility;
+
+    #[test]
+    fn test_mlp_dump_and_load() {
+        let model = MlpRegressor::new(4, 1);
+        let field = utility::filled_field(5, Some(1));
+        let loaded = load_model_direct(&model.dump());
+        assert!((model.predict(&field) - loaded.predict(&field)).abs() < 1e-4);
+        assert!(model.predict(&field) != 0.0);
+    }
+
+    #[test]
+    fn test_explain() {
+        let layout = feature_layout();
+        let field = utility::filled_field(5, Some(2));
+        let feature = extract_feature(&field);
+        assert_eq!(layout.len(), feature.len());
+        assert!(layout.iter().zip(feature).all(|(f, active)| f.is_active(&field) == active));
+
+        let model = test_regressor();
+        let explanation = model.explain(&field);
+        assert_eq!(explanation.value, model.predict(&field));
+        let by_offset: f32 = explanation.by_offset.iter().flatten().sum();
+        assert_eq!(by_offset, explanation.value);
+        assert_eq!(explanation.region(0..HEIGHT, 0..WIDTH), explanation.value);
+
+        let base = utility::filled_field(3, Some(2));
+        let diff = model.explain_diff(&field, &base);
+        assert_eq!(diff.value, model.predict(&field) - model.predict(&base));
+        let base_features = model.explain(&base).features.len();
+        assert!(diff.features.len() < explanation.features.len() + base_features);
+        assert!(model.explain_diff(&field, &field).features.is_empty());
+    }
 }
@@ -0,0 +1,200 @@
+//! Module for replay files, from which a whole game can be reproduced.
+//!
+//! A game is determined by the piece sequence, the initial field and the commands, so a replay
+//! file stores the session information (from which the first two are generated) and the command
+//! list of each piece. It is in the JSON lines format: the first line is a `ReplayHeader` and each
+//! of the following lines is the command list of a piece. Pieces are appended one by one, so a
+//! replay of an interrupted game is still readable. Since version 2, a line can also be a
+//! retraction `{"retract": n}`, which leaves only the first `n` pieces when a piece is undone.
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use serde_json;
+
+use core::{apply_command, new_piece, Command, CommandResult, Field, PieceState, ScoreInfo};
+use logger::SessionInfo;
+
+/// The current version of the replay format.
+pub const REPLAY_VERSION: u32 = 2;
+
+/// The first line of a replay file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub session: SessionInfo,
+}
+
+/// A line of a replay file taking back pieces.
+#[derive(Serialize, Deserialize)]
+struct Retraction {
+    /// The number of pieces left.
+    retract: usize,
+}
+
+/// A struct for writing replay files.
+pub struct ReplayWriter {
+    file: File,
+}
+
+impl ReplayWriter {
+    pub fn create(filename: &str, session: &SessionInfo) -> ReplayWriter {
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(filename)
+            .unwrap();
+        let mut writer = ReplayWriter { file };
+        let header = ReplayHeader {
+            version: REPLAY_VERSION,
+            session: session.clone(),
+        };
+        writer.write_line(&serde_json::to_string(&header).unwrap());
+        writer
+    }
+
+    /// Appends the commands of a piece, which must end with a command fixing it.
+    pub fn write_piece(&mut self, commands: &[Command]) {
+        self.write_line(&serde_json::to_string(commands).unwrap());
+    }
+
+    /// Takes back the pieces after the first `pieces` ones.
+    pub fn retract(&mut self, pieces: usize) {
+        self.write_line(&serde_json::to_string(&Retraction { retract: pieces }).unwrap());
+    }
+
+    fn write_line(&mut self, line: &str) {
+        self.file
+            .write_all(format!("{}\n", line).as_bytes())
+            .unwrap();
+    }
+}
+
+/// A state of the game at some point of a replay.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub field: Field,
+    pub state: PieceState,
+    /// Index of the current piece.
+    pub piece: usize,
+    pub next_piece: u8,
+    pub score_info: ScoreInfo,
+}
+
+/// A loaded replay.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub pieces: Vec<Vec<Command>>,
+}
+
+impl Replay {
+    pub fn load(filename: &str) -> Replay {
+        let file = OpenOptions::new().read(true).open(filename).unwrap();
+        let mut lines = BufReader::new(file).lines().map(|line| line.unwrap());
+        let header: ReplayHeader = serde_json::from_str(&lines.next().unwrap()).unwrap();
+        assert!(
+            header.version <= REPLAY_VERSION,
+            "{}: unsupported replay version {}",
+            filename,
+            header.version
+        );
+        let mut pieces = vec![];
+        for line in lines {
+            match serde_json::from_str(&line) {
+                Ok(commands) => pieces.push(commands),
+                Err(e) => {
+                    let retraction: Retraction =
+                        serde_json::from_str(&line).unwrap_or_else(|_| panic!("{}", e));
+                    pieces.truncate(retraction.retract);
+                }
+            }
+        }
+        Replay { header, pieces }
+    }
+
+    /// Reproduces the game and returns the states before each command and the final state.
+    /// Returns an error if a command list does not end with fixing the piece, or the game ends
+    /// in the middle of the replay.
+    pub fn frames(&self) -> Result<Vec<Frame>, String> {
+        let session = &self.header.session;
+        let seq = session.pieces(self.pieces.len() + 2);
+        let mut frame = Frame {
+            field: session.initial_field(),
+            state: new_piece(seq[0]),
+            piece: 0,
+            next_piece: seq[1],
+            score_info: ScoreInfo::new(),
+        };
+        let mut frames = vec![];
+        for (piece, commands) in self.pieces.iter().enumerate() {
+            let mut fixed = false;
+            for command in commands.iter() {
+                if fixed {
+                    return Err(format!("piece {}: commands after fixing", piece));
+                }
+                frames.push(frame.clone());
+                match apply_command(&frame.field, &frame.state, command) {
+                    CommandResult::Moved(state, _) => frame.state = state,
+                    CommandResult::Fixed(info) => {
+                        frame.field = info.new_field;
+                        frame.score_info.update(info.del);
+                        frame.piece += 1;
+                        frame.state = new_piece(seq[frame.piece]);
+                        frame.next_piece = seq[frame.piece + 1];
+                        fixed = true;
+                    }
+                    CommandResult::Ended => return Err(format!("piece {}: game over", piece)),
+                }
+            }
+            if !fixed {
+                return Err(format!("piece {}: not fixed", piece));
+            }
+        }
+        frames.push(frame);
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use core::{fix_piece, Rules};
+    use enumeration::{enumerate_single, find_command_sequence};
+    use utility;
+
+    #[test]
+    fn test_write_and_replay() {
+        let path = env::temp_dir().join("tetris20g_ai_test_replay.txt");
+        let _ = fs::remove_file(&path);
+        let session = SessionInfo {
+            seed: 3,
+            randomizer: String::from(utility::RANDOMIZER),
+            rules: Rules::default(),
+            lines: 4,
+            player: None,
+            date: String::from("2017-11-01T00:00:00+09:00"),
+            input_profile: None,
+            position: None,
+        };
+        let seq = utility::generate_pieces(5, Some(session.seed));
+        let mut field = utility::filled_field(session.lines, Some(session.seed));
+        {
+            let mut writer = ReplayWriter::create(path.to_str().unwrap(), &session);
+            for &piece in seq.iter().take(3) {
+                let dest = enumerate_single(&field, piece).pop().unwrap().last_state;
+                writer.write_piece(&find_command_sequence(&field, piece, &dest));
+                field = fix_piece(&field, &dest).0;
+            }
+        }
+
+        let replay = Replay::load(path.to_str().unwrap());
+        assert_eq!(replay.header.session, session);
+        let frames = replay.frames().unwrap();
+        let last = frames.last().unwrap();
+        assert_eq!(last.field, field);
+        assert_eq!(last.piece, 3);
+        assert_eq!(last.score_info.steps, 3);
+        fs::remove_file(&path).unwrap();
+    }
+}
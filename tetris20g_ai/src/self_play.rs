@@ -0,0 +1,183 @@
+//! Module for learning a value model from self-play by temporal-difference targets.
+//!
+//! Trajectories are generated by the two-step search with the current model. The value of an
+//! afterstate (a field right after a piece is fixed) is the discounted number of pieces the
+//! agent can still place, so the reward is 1 for every successfully placed piece and a
+//! trajectory which reached game over ends with value 0.
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use agent::two_step_search;
+use core::{self, Field};
+use enumeration::enumerate_single;
+use episode::run_parallel;
+use regressor::{TrainableModel, ValueModel};
+use utility;
+
+/// Afterstates visited in one episode.
+pub struct Trajectory {
+    pub afterstates: Vec<Field>,
+    /// This is false if the episode was truncated by the step limit.
+    pub ended: bool,
+}
+
+/// How targets are computed from a trajectory.
+#[derive(Debug, Clone, Copy)]
+pub enum TargetKind {
+    /// TD(lambda) return with a given lambda.
+    Lambda(f32),
+    /// n-step return.
+    NStep(usize),
+}
+
+/// Settings of the self-play learning loop.
+pub struct SelfPlayConfig {
+    pub episodes: usize,
+    pub max_steps: usize,
+    pub lines: usize,
+    /// Probability of choosing a random placement instead of the greedy one.
+    pub epsilon: f64,
+    pub gamma: f32,
+    pub target: TargetKind,
+    pub learning_rate: f32,
+    /// The number of passes over the collected samples in an iteration.
+    pub epochs: usize,
+    pub threads: usize,
+    pub seed: u32,
+}
+
+/// Plays one episode with the two-step search and epsilon-greedy exploration.
+pub fn generate_trajectory<M: ValueModel + ?Sized>(
+    model: &M,
+    seed: u32,
+    lines: usize,
+    max_steps: usize,
+    epsilon: f64,
+) -> Trajectory {
+    let mut rng: XorShiftRng = SeedableRng::from_seed([seed, 0x6a09_e667, 0xbb67_ae85, 3]);
+    let mut field = utility::filled_field(lines, Some(seed));
+    let pieces = utility::generate_pieces(max_steps + 2, Some(seed));
+    let mut afterstates = vec![];
+
+    for step in 0..max_steps {
+        let next_piece = pieces[step];
+        let next2_piece = pieces[step + 1];
+        let state = if rng.gen::<f64>() < epsilon {
+            let candidates = enumerate_single(&field, next_piece);
+            rng.choose(&candidates).map(|info| info.last_state.clone())
+        } else {
+            two_step_search(model, &field, next_piece, next2_piece).map(|(state, _)| state)
+        };
+        let state = match state {
+            None => return Trajectory { afterstates, ended: true },
+            Some(state) => state,
+        };
+        let (new_field, _) = core::fix_piece(&field, &state);
+        field = new_field;
+        afterstates.push(field);
+    }
+    Trajectory { afterstates, ended: false }
+}
+
+/// Returns the target of every afterstate. `values` are the current predictions.
+pub fn compute_targets(values: &[f32], ended: bool, gamma: f32, target: TargetKind) -> Vec<f32> {
+    let n = values.len();
+    if n == 0 {
+        return vec![];
+    }
+    // value of the last afterstate: game over follows, or bootstrapping for truncation
+    let last = if ended { 0.0 } else { values[n - 1] };
+    let mut targets = vec![0.0; n];
+    match target {
+        TargetKind::Lambda(lambda) => {
+            targets[n - 1] = last;
+            for t in (0..n - 1).rev() {
+                let next_value = if t + 1 == n - 1 { last } else { values[t + 1] };
+                targets[t] = 1.0 + gamma * ((1.0 - lambda) * next_value + lambda * targets[t + 1]);
+            }
+        }
+        TargetKind::NStep(steps) => {
+            for (t, target) in targets.iter_mut().enumerate() {
+                let horizon = (t + steps).min(n - 1);
+                let mut ret = 0.0;
+                let mut discount = 1.0;
+                for _ in t..horizon {
+                    ret += discount;
+                    discount *= gamma;
+                }
+                let tail = if horizon == n - 1 { last } else { values[horizon] };
+                *target = ret + discount * tail;
+            }
+        }
+    }
+    targets
+}
+
+/// Summary of one iteration of self-play learning.
+pub struct IterationSummary {
+    pub average_steps: f64,
+    pub samples: usize,
+    pub mean_squared_error: f64,
+}
+
+/// Runs one iteration: generates trajectories with the current model in parallel,
+/// computes targets and fits the model to them.
+pub fn run_iteration<M: TrainableModel + ?Sized>(
+    model: &mut M,
+    config: &SelfPlayConfig,
+    iteration: usize,
+) -> IterationSummary {
+    let seeds: Vec<u32> = (0..config.episodes)
+        .map(|k| config.seed.wrapping_add((iteration * config.episodes + k) as u32 + 1))
+        .collect();
+    let trajectories = {
+        let model: &M = model;
+        run_parallel(&seeds, config.threads, |&seed| {
+            generate_trajectory(model, seed, config.lines, config.max_steps, config.epsilon)
+        })
+    };
+
+    let mut samples: Vec<(Field, f32)> = vec![];
+    for trajectory in trajectories.iter() {
+        let values: Vec<f32> = trajectory.afterstates.iter().map(|f| model.predict(f)).collect();
+        let targets = compute_targets(&values, trajectory.ended, config.gamma, config.target);
+        samples.extend(trajectory.afterstates.iter().cloned().zip(targets));
+    }
+
+    let mut rng: XorShiftRng =
+        SeedableRng::from_seed([config.seed, iteration as u32, 0x3c6e_f372, 5]);
+    let mut squared_error = 0.0;
+    for _ in 0..config.epochs {
+        rng.shuffle(&mut samples);
+        squared_error = 0.0;
+        for &(ref field, target) in samples.iter() {
+            squared_error += ((target - model.predict(field)) as f64).powi(2);
+            model.sgd_update(field, target, config.learning_rate);
+        }
+    }
+
+    let total_steps: usize = trajectories.iter().map(|t| t.afterstates.len()).sum();
+    IterationSummary {
+        average_steps: total_steps as f64 / trajectories.len().max(1) as f64,
+        samples: samples.len(),
+        mean_squared_error: squared_error / samples.len().max(1) as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_targets() {
+        let values = [5.0, 4.0, 3.0];
+        // lambda = 1 is the Monte Carlo return
+        let targets = compute_targets(&values, true, 1.0, TargetKind::Lambda(1.0));
+        assert_eq!(targets, vec![2.0, 1.0, 0.0]);
+        // 1-step return bootstraps from the next value
+        let targets = compute_targets(&values, true, 0.5, TargetKind::NStep(1));
+        assert_eq!(targets, vec![3.0, 1.0, 0.0]);
+        // lambda = 0 equals 1-step return
+        let targets = compute_targets(&values, false, 0.5, TargetKind::Lambda(0.0));
+        assert_eq!(targets, compute_targets(&values, false, 0.5, TargetKind::NStep(1)));
+    }
+}
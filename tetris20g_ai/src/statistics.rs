@@ -0,0 +1,141 @@
+//! Module for statistics of episode results.
+//!
+//! Game lengths are heavy-tailed, so medians and quantiles are often more informative than
+//! means, and episodes truncated by a step limit are censored observations rather than deaths.
+//! `kaplan_meier` and `fit_geometric` take the censoring into account.
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+/// Returns the `q`-quantile (0 to 1) of given values, interpolating between the closest ranks.
+pub fn quantile(values: &[f64], q: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    sorted_quantile(&sorted, q)
+}
+
+pub fn median(values: &[f64]) -> f64 {
+    quantile(values, 0.5)
+}
+
+fn sorted_quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// Returns the percentile bootstrap confidence interval of a statistic, e.g.,
+/// `bootstrap_ci(&values, median, 0.95, 1000, 0)`. The resampling is determined by `seed`.
+pub fn bootstrap_ci<F>(values: &[f64], statistic: F, confidence: f64, resamples: usize, seed: u32)
+    -> (f64, f64)
+where
+    F: Fn(&[f64]) -> f64,
+{
+    let mut rng: XorShiftRng = SeedableRng::from_seed([seed, 1, 2, 3]);
+    let mut resample = vec![0.0; values.len()];
+    let mut estimates: Vec<f64> = (0..resamples)
+        .map(|_| {
+            for x in resample.iter_mut() {
+                *x = values[rng.gen_range(0, values.len())];
+            }
+            statistic(&resample)
+        })
+        .collect();
+    estimates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let alpha = (1.0 - confidence) / 2.0;
+    (sorted_quantile(&estimates, alpha), sorted_quantile(&estimates, 1.0 - alpha))
+}
+
+/// A step of a Kaplan-Meier survival curve.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SurvivalPoint {
+    pub time: usize,
+    /// The number of episodes alive just before `time`.
+    pub at_risk: usize,
+    /// The number of episodes ended at `time`.
+    pub deaths: usize,
+    /// The estimated probability of surviving beyond `time`.
+    pub survival: f64,
+}
+
+/// Returns the Kaplan-Meier estimate of the survival function from pairs of the length of an
+/// episode and whether it ended, i.e., false if it was truncated. The curve has a point at each
+/// time with deaths.
+pub fn kaplan_meier(lengths: &[(usize, bool)]) -> Vec<SurvivalPoint> {
+    let mut sorted = lengths.to_vec();
+    sorted.sort();
+    let mut curve = vec![];
+    let mut survival = 1.0;
+    let mut i = 0;
+    while i < sorted.len() {
+        let time = sorted[i].0;
+        let j = (i..sorted.len()).find(|&j| sorted[j].0 != time).unwrap_or(sorted.len());
+        let at_risk = sorted.len() - i;
+        let deaths = sorted[i..j].iter().filter(|&&(_, ended)| ended).count();
+        if deaths > 0 {
+            survival *= 1.0 - deaths as f64 / at_risk as f64;
+            curve.push(SurvivalPoint { time, at_risk, deaths, survival });
+        }
+        i = j;
+    }
+    curve
+}
+
+/// Returns the first time where the survival falls to a half or below, if any.
+pub fn median_survival(curve: &[SurvivalPoint]) -> Option<usize> {
+    curve.iter().find(|point| point.survival <= 0.5).map(|point| point.time)
+}
+
+/// Maximum likelihood fit of a geometric distribution, where each piece ends the game with a
+/// constant probability.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeometricFit {
+    /// The probability that a piece cannot be placed.
+    pub death_rate: f64,
+    /// The expected number of placed pieces, `(1 - p) / p`.
+    pub expected_length: f64,
+}
+
+/// Fits a geometric distribution to episode lengths as in `kaplan_meier`. An ended episode of
+/// length `n` failed at the `n + 1`-th piece, and a truncated one survived `n` pieces. Returns
+/// None if no episode ended.
+pub fn fit_geometric(lengths: &[(usize, bool)]) -> Option<GeometricFit> {
+    let deaths = lengths.iter().filter(|&&(_, ended)| ended).count();
+    if deaths == 0 {
+        return None;
+    }
+    let trials: usize = lengths.iter().map(|&(n, ended)| if ended { n + 1 } else { n }).sum();
+    let death_rate = deaths as f64 / trials as f64;
+    Some(GeometricFit {
+        death_rate,
+        expected_length: (1.0 - death_rate) / death_rate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statistics() {
+        let values = [4.0, 1.0, 3.0, 2.0, 5.0];
+        assert_eq!(median(&values), 3.0);
+        assert_eq!(quantile(&values, 0.1), 1.4);
+        assert_eq!(quantile(&values, 1.0), 5.0);
+        let (low, high) = bootstrap_ci(&values, median, 0.9, 200, 0);
+        assert!((1.0..=3.0).contains(&low) && (3.0..=5.0).contains(&high));
+        assert_eq!(bootstrap_ci(&values, median, 0.9, 200, 0), (low, high));
+
+        // (length, ended)
+        let lengths = [(3, true), (5, false), (5, true), (8, true), (10, false), (10, false)];
+        let curve = kaplan_meier(&lengths);
+        let times: Vec<usize> = curve.iter().map(|p| p.time).collect();
+        assert_eq!(times, vec![3, 5, 8]);
+        assert!((curve[1].survival - 5.0 / 6.0 * 4.0 / 5.0).abs() < 1e-9);
+        assert!((curve[2].survival - 4.0 / 9.0).abs() < 1e-9);
+        assert_eq!(median_survival(&curve), Some(8));
+
+        let fit = fit_geometric(&lengths).unwrap();
+        assert!((fit.death_rate - 3.0 / 44.0).abs() < 1e-9);
+        assert_eq!(fit_geometric(&[(10, false)]), None);
+    }
+}
@@ -3,11 +3,32 @@ use rand::{thread_rng, Rng, SeedableRng, XorShiftRng};
 
 use core;
 
+/// Name of the piece randomizer implemented by `generate_pieces`, which is recorded in logs.
+pub const RANDOMIZER: &str = "xorshift-uniform";
+
+/// Returns a seed chosen at random, so that a game can be recorded with its seed.
+pub fn random_seed() -> u32 {
+    let mut rng = thread_rng();
+    loop {
+        let seed = rng.gen::<u32>();
+        if seed != 0 {
+            return seed;
+        }
+    }
+}
+
+/// Parses a seed for `generate_pieces` and `filled_field`. 0 is rejected, since `XorShiftRng`
+/// cannot be seeded by zeros.
+pub fn parse_seed(s: &str) -> Result<u32, String> {
+    match s.trim().parse::<u32>() {
+        Ok(0) => Err(String::from("seed must not be 0")),
+        Ok(seed) => Ok(seed),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 pub fn generate_pieces(len: usize, seed: Option<u32>) -> Vec<u8> {
-    let seed = match seed {
-        None => thread_rng().gen::<u32>(),
-        Some(seed) => seed,
-    };
+    let seed = seed.unwrap_or_else(random_seed);
     let mut rng: XorShiftRng = SeedableRng::from_seed([seed; 4]);
     let m: Vec<u8> = "IOSZJLT".bytes().collect();
     let mut seq = vec![];
@@ -18,10 +39,7 @@ pub fn generate_pieces(len: usize, seed: Option<u32>) -> Vec<u8> {
 }
 
 pub fn filled_field(lines: usize, seed: Option<u32>) -> core::Field {
-    let seed = match seed {
-        None => thread_rng().gen::<u32>(),
-        Some(seed) => seed,
-    };
+    let seed = seed.unwrap_or_else(random_seed);
     let mut rng: XorShiftRng = SeedableRng::from_seed([seed; 4]);
 
     let mut field = core::EMPTY_FIELD;
@@ -33,6 +51,15 @@ pub fn filled_field(lines: usize, seed: Option<u32>) -> core::Field {
     field
 }
 
+/// Returns the height of the stack, i.e., the number of rows from the bottom to the highest
+/// filled cell.
+pub fn stack_height(field: &core::Field) -> usize {
+    field
+        .iter()
+        .position(|row| row.iter().any(|&cell| cell != b'.'))
+        .map_or(0, |i| core::HEIGHT - i)
+}
+
 /// Returns the average and the standard deviation of given values.
 pub fn statistics(scores: &Vec<f64>) -> (f64, f64) {
     let n = scores.len() as f64;
@@ -48,6 +75,14 @@ pub fn statistics(scores: &Vec<f64>) -> (f64, f64) {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_seed() {
+        assert_eq!(parse_seed("42"), Ok(42));
+        assert!(parse_seed("0").is_err());
+        assert!(parse_seed("x").is_err());
+        assert_ne!(random_seed(), 0);
+    }
+
     #[test]
     fn test_statistics() {
         let scores = vec![1.0, 2.0, 3.0, 10.0];
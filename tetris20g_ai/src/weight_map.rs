@@ -0,0 +1,200 @@
+//! Module for inspecting the weights of `LinearRegressor` by their structure.
+//!
+//! The weights are laid out as in `feature_layout`, i.e., by the first cell `(i, j)`, the offset
+//! `(di, dj)` of the second cell and the polarity. A `WeightMap` collects the weights of an offset
+//! and a polarity on the cells of the field, so that a map shows where in the field the pattern
+//! is rewarded or penalized. Maps are rendered as PPM or SVG images, red for negative and green
+//! for positive weights.
+use std::fs::{self, File};
+use std::io::Write;
+
+use core::{HEIGHT, WIDTH};
+use regressor::{feature_layout, Polarity, MAX_DI, MAX_DJ};
+
+/// Weights of the features of an offset and a polarity by their first cells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightMap {
+    pub di: usize,
+    pub dj: i8,
+    pub polarity: Polarity,
+    /// `HEIGHT` rows of `WIDTH` cells. None where the second cell is out of the field.
+    pub values: Vec<Vec<Option<f32>>>,
+}
+
+impl WeightMap {
+    pub fn label(&self) -> String {
+        let polarity = match self.polarity {
+            Polarity::EmptyAboveFilled => "empty-filled",
+            Polarity::FilledAboveEmpty => "filled-empty",
+        };
+        format!("di={} dj={} {}", self.di, self.dj, polarity)
+    }
+
+    fn defined(&self) -> Vec<f32> {
+        self.values.iter().flatten().filter_map(|&v| v).collect()
+    }
+
+    /// Returns the minimum, mean and maximum of the weights.
+    pub fn summary(&self) -> (f32, f32, f32) {
+        let values = self.defined();
+        let min = values.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        (min, values.iter().sum::<f32>() / values.len() as f32, max)
+    }
+
+    /// The maximum absolute weight among finite ones, so that colors are not scaled by infinity.
+    pub fn max_abs(&self) -> f32 {
+        self.defined().iter().filter(|v| v.is_finite()).fold(0.0, |max, v| max.max(v.abs()))
+    }
+}
+
+/// Decodes weights into maps, ordered by the polarity, `di` and `dj`. Returns an error if the
+/// number of weights does not match `feature_layout`.
+pub fn weight_maps(params: &[f32]) -> Result<Vec<WeightMap>, String> {
+    let layout = feature_layout();
+    if params.len() != layout.len() {
+        return Err(format!("{} weights, but there are {} features", params.len(), layout.len()));
+    }
+    let mut maps = vec![];
+    for &polarity in [Polarity::EmptyAboveFilled, Polarity::FilledAboveEmpty].iter() {
+        for di in 0..MAX_DI {
+            for dj in -MAX_DJ..MAX_DJ + 1 {
+                if di == 0 && dj == 0 {
+                    continue;
+                }
+                let values = vec![vec![None; WIDTH]; HEIGHT];
+                maps.push(WeightMap { di, dj, polarity, values });
+            }
+        }
+    }
+    for (feature, &weight) in layout.iter().zip(params.iter()) {
+        let map = maps
+            .iter_mut()
+            .find(|m| (m.di, m.dj, m.polarity) == (feature.di, feature.dj, feature.polarity))
+            .unwrap();
+        map.values[feature.i][feature.j] = Some(weight);
+    }
+    Ok(maps)
+}
+
+/// Returns the color of a weight scaled by `max`: black for zero, red for negative and green for
+/// positive weights, and gray where it is undefined.
+pub fn color(value: Option<f32>, max: f32) -> (u8, u8, u8) {
+    match value {
+        None => (64, 64, 64),
+        Some(v) => {
+            let t = if max > 0.0 { (v.abs() / max).min(1.0) } else { 0.0 };
+            let level = (255.0 * t).round() as u8;
+            if v < 0.0 { (level, 0, 0) } else { (0, level, 0) }
+        }
+    }
+}
+
+/// Tiles of maps in images: a row for each polarity and `di`, and a column for each `dj`.
+fn tile_position(map: &WeightMap) -> (usize, usize) {
+    let polarity = match map.polarity {
+        Polarity::EmptyAboveFilled => 0,
+        Polarity::FilledAboveEmpty => 1,
+    };
+    (polarity * MAX_DI + map.di, (map.dj + MAX_DJ) as usize)
+}
+
+const TILE_ROWS: usize = 2 * MAX_DI;
+const TILE_COLUMNS: usize = 2 * MAX_DJ as usize + 1;
+
+/// Writes maps into a binary PPM image, in which a cell is `scale` pixels square and tiles are
+/// separated by a cell. Colors are scaled by the maximum absolute weight of all maps.
+pub fn write_ppm(maps: &[WeightMap], scale: usize, filename: &str) {
+    let max = maps.iter().fold(0.0, |max: f32, m| max.max(m.max_abs()));
+    let (tile_width, tile_height) = ((WIDTH + 1) * scale, (HEIGHT + 1) * scale);
+    let (width, height) = (TILE_COLUMNS * tile_width, TILE_ROWS * tile_height);
+    let mut pixels = vec![255u8; width * height * 3];
+    for map in maps {
+        let (row, column) = tile_position(map);
+        for (i, cells) in map.values.iter().enumerate() {
+            for (j, &value) in cells.iter().enumerate() {
+                let (r, g, b) = color(value, max);
+                for y in 0..scale {
+                    for x in 0..scale {
+                        let py = row * tile_height + i * scale + y;
+                        let px = column * tile_width + j * scale + x;
+                        let index = (py * width + px) * 3;
+                        pixels[index..index + 3].copy_from_slice(&[r, g, b]);
+                    }
+                }
+            }
+        }
+    }
+    let mut file = File::create(filename).unwrap();
+    file.write_all(format!("P6\n{} {}\n255\n", width, height).as_bytes()).unwrap();
+    file.write_all(&pixels).unwrap();
+}
+
+/// Writes maps into an SVG image laid out as in `write_ppm`, with labels and the value of each
+/// cell as a tooltip.
+pub fn write_svg(maps: &[WeightMap], scale: usize, filename: &str) {
+    let max = maps.iter().fold(0.0, |max: f32, m| max.max(m.max_abs()));
+    let (tile_width, tile_height) = ((WIDTH + 1) * scale, (HEIGHT + 2) * scale);
+    let (width, height) = (TILE_COLUMNS * tile_width, TILE_ROWS * tile_height);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         font-family=\"monospace\" font-size=\"{}\">\n",
+        width, height, scale
+    );
+    for map in maps {
+        let (row, column) = tile_position(map);
+        let (left, top) = (column * tile_width, row * tile_height);
+        svg += &format!("<text x=\"{}\" y=\"{}\">{}</text>\n", left, top + scale, map.label());
+        for (i, cells) in map.values.iter().enumerate() {
+            for (j, &value) in cells.iter().enumerate() {
+                let (r, g, b) = color(value, max);
+                let title = value.map_or(String::from("none"), |v| v.to_string());
+                svg += &format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"rgb({},{},{})\">\
+                     <title>({}, {}) {}</title></rect>\n",
+                    left + j * scale, top + (i + 1) * scale, scale, scale, r, g, b, i, j, title
+                );
+            }
+        }
+    }
+    svg += "</svg>\n";
+    fs::write(filename, svg).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Read;
+
+    #[test]
+    fn test_weight_maps() {
+        let layout = feature_layout();
+        let params: Vec<f32> = (0..layout.len()).map(|k| k as f32).collect();
+        let maps = weight_maps(&params).unwrap();
+        assert_eq!(maps.len(), 2 * (TILE_ROWS / 2 * TILE_COLUMNS - 1));
+        for (k, feature) in layout.iter().enumerate().step_by(97) {
+            let map = maps
+                .iter()
+                .find(|m| (m.di, m.dj, m.polarity) == (feature.di, feature.dj, feature.polarity))
+                .unwrap();
+            assert_eq!(map.values[feature.i][feature.j], Some(k as f32));
+        }
+        // the second cell of (di, dj) = (3, -3) is out of the field at the bottom and the left
+        let map = maps.iter().find(|m| (m.di, m.dj) == (3, -3)).unwrap();
+        assert_eq!(map.values[HEIGHT - 1][WIDTH - 1], None);
+        assert_eq!(map.values[0][0], None);
+        assert!(map.values[0][3].is_some());
+        assert!(weight_maps(&params[1..]).is_err());
+
+        let path = env::temp_dir().join("tetris20g_ai_test_weight_map.ppm");
+        write_ppm(&maps, 2, path.to_str().unwrap());
+        let mut data = vec![];
+        File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+        let (width, height) = (TILE_COLUMNS * (WIDTH + 1) * 2, TILE_ROWS * (HEIGHT + 1) * 2);
+        let header = format!("P6\n{} {}\n255\n", width, height);
+        assert!(data.starts_with(header.as_bytes()));
+        assert_eq!(data.len(), header.len() + width * height * 3);
+        fs::remove_file(&path).unwrap();
+    }
+}